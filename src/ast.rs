@@ -1,36 +1,263 @@
+use crate::lexer::Position;
+use crate::symbol::Symbol;
+use std::cell::{Cell, RefCell};
 use std::fmt;
 use std::fmt::Formatter;
+use std::rc::Rc;
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Expression {
-    Identifier(String),
+    Identifier(Variable, Position),
     IntegerLiteral(i64),
     String(String),
     Bool(bool),
-    Prefix(Prefix, Box<Expression>),
-    Infix(Infix, Box<Expression>, Box<Expression>),
+    Null,
+    Prefix(Prefix, Box<Expression>, Position),
+    Assign(Variable, Box<Expression>, Position),
+    IndexAssign(Box<Expression>, Box<Expression>, Box<Expression>, Position),
+    Infix(Infix, Box<Expression>, Box<Expression>, Position),
+    /// `if (a) {..} else if (b) {..} else {..}` as the condition, where it
+    /// starts and block of each branch in order, and the final `else` block
     If(
-        Box<Expression>,
-        Box<BlockStatement>,
+        Vec<(Expression, Position, BlockStatement)>,
         Option<Box<BlockStatement>>,
     ),
-    FunctionLiteral(Vec<String>, Box<BlockStatement>),
-    HashMapLiteral(Vec<(Expression, Expression)>),
+    /// `condition ? a : b`, positioned at the `?`
+    Ternary(Box<Expression>, Box<Expression>, Box<Expression>, Position),
+    FunctionLiteral(Rc<Function>),
+    /// Each key, its value and where the key starts
+    HashMapLiteral(Vec<(Expression, Expression, Position)>),
     ArrayLiteral(Vec<Expression>),
-    Index(Box<Expression>, Box<Expression>),
-    Call(Box<Expression>, Vec<Expression>),
+    TupleLiteral(Vec<Expression>),
+    Index(Box<Expression>, Box<Expression>, Position),
+    Field(Box<Expression>, Symbol, Position),
+    /// `a?.x`, which is null when `a` is
+    OptionalField(Box<Expression>, Symbol, Position),
+    FieldAssign(Box<Expression>, Symbol, Box<Expression>, Position),
+    StructLiteral(Variable, Vec<(Symbol, Expression)>, Position),
+    /// The `start:end` of `a[start:end]`, only ever parsed as an index
+    Slice(Option<Box<Expression>>, Option<Box<Expression>>),
+    OptionalIndex(Box<Expression>, Box<Expression>, Position),
+    Call(Box<Expression>, Vec<Argument>, Position),
+    MethodCall(Box<Expression>, Symbol, Vec<Argument>, Position),
+    OptionalCall(Box<Expression>, Vec<Argument>, Position),
+    Try(
+        Box<BlockStatement>,
+        Option<CatchClause>,
+        Option<Box<BlockStatement>>,
+    ),
+    Match(Box<Expression>, Vec<MatchArm>, Position),
+    /// A `{ ... }` block used as a value, like `let v = { let t = f(); t * 2 };`
+    Block(Box<BlockStatement>),
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Statement {
-    Let(String, Box<Expression>),
+    /// A `let` whose variables can't be assigned
+    Let(Pattern, Box<Expression>, Position),
+    /// A `let mut`, whose variables can be assigned
+    LetMut(Pattern, Box<Expression>, Position),
+    /// A `let` whose variables can't be assigned or declared again
+    Const(Pattern, Box<Expression>, Position),
     Return(Option<Box<Expression>>),
+    Throw(Box<Expression>, Position),
+    Function(Variable, Rc<Function>),
+    Struct(Variable, Rc<StructDef>),
+    Enum(Variable, Rc<EnumDef>),
     Expression(Box<Expression>),
 }
 
+///
+///  Where a variable lives, as found by the resolver. Globals stay keyed by
+///  name so the REPL can keep adding them, locals are a slot in a scope
+///  `depth` scopes out from the one using them
+///
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Binding {
+    Global,
+    Local { depth: usize, slot: usize },
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Variable {
+    pub name: Symbol,
+    pub binding: Cell<Binding>,
+}
+
+impl Variable {
+    pub fn new(name: &str) -> Self {
+        Variable {
+            name: Symbol::intern(name),
+            binding: Cell::new(Binding::Global),
+        }
+    }
+}
+
+/// The names of a scope's local slots in slot order, filled in by the resolver
+pub type Locals = RefCell<Rc<[Symbol]>>;
+
+#[derive(Debug, PartialEq)]
+pub struct Function {
+    pub params: Vec<Parameter>,
+    pub body: BlockStatement,
+    pub locals: Locals,
+}
+
+///
+///  A struct declaration. Its methods are functions whose first parameter is
+///  the value they are called on
+///
+#[derive(Debug, PartialEq)]
+pub struct StructDef {
+    pub name: Symbol,
+    pub fields: Vec<Symbol>,
+    pub methods: Vec<(Symbol, Rc<Function>)>,
+}
+
+impl StructDef {
+    pub fn field_position(&self, field: Symbol) -> Option<usize> {
+        self.fields.iter().position(|f| *f == field)
+    }
+
+    pub fn method(&self, name: Symbol) -> Option<&Rc<Function>> {
+        self.methods
+            .iter()
+            .find(|(method, _)| *method == name)
+            .map(|(_, function)| function)
+    }
+}
+
+///
+///  An enum declaration. Each variant has a name and the names of the values
+///  it holds, none for a variant like `Empty`
+///
+#[derive(Debug, PartialEq)]
+pub struct EnumDef {
+    pub name: Symbol,
+    pub variants: Vec<(Symbol, Vec<Symbol>)>,
+}
+
+impl EnumDef {
+    pub fn variant(&self, name: Symbol) -> Option<usize> {
+        self.variants
+            .iter()
+            .position(|(variant, _)| *variant == name)
+    }
+}
+
+///
+///  One `pattern if guard => body` arm of a match. The variables the
+///  pattern binds live in a scope of the arm's own
+///
+#[derive(Debug, PartialEq, Clone)]
+pub struct MatchArm {
+    pub pattern: Pattern,
+    pub guard: Option<Expression>,
+    pub body: BlockStatement,
+    pub locals: Locals,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Pattern {
+    /// `_`, which matches anything
+    Wildcard,
+    Binding(Variable),
+    /// An integer, string, boolean or null literal
+    Literal(Expression),
+    /// `[a, b, ...rest]`, where the rest pattern matches an array of the
+    /// remaining items
+    Array(Vec<Pattern>, Option<Box<Pattern>>),
+    Tuple(Vec<Pattern>),
+    /// `{"key": pattern, name}`, matching hashmaps that have every key
+    HashMap(Vec<(Expression, Pattern)>),
+    Struct(Variable, Vec<(Symbol, Pattern)>, Position),
+    /// `Shape.Circle(r)`, or `Shape.Empty` for a variant holding no values
+    Variant(Variable, Symbol, Vec<Pattern>, Position),
+}
+
+impl Pattern {
+    /// The variables this pattern binds, in the order they appear
+    pub fn bindings(&self) -> Vec<&Variable> {
+        let mut bindings = vec![];
+        self.collect_bindings(&mut bindings);
+        bindings
+    }
+
+    fn collect_bindings<'a>(&'a self, bindings: &mut Vec<&'a Variable>) {
+        match self {
+            Pattern::Binding(variable) => bindings.push(variable),
+            Pattern::Array(items, rest) => {
+                items.iter().for_each(|p| p.collect_bindings(bindings));
+                if let Some(rest) = rest {
+                    rest.collect_bindings(bindings);
+                }
+            }
+            Pattern::Tuple(items) | Pattern::Variant(_, _, items, _) => {
+                items.iter().for_each(|p| p.collect_bindings(bindings))
+            }
+            Pattern::HashMap(entries) => entries
+                .iter()
+                .for_each(|(_, p)| p.collect_bindings(bindings)),
+            Pattern::Struct(_, fields, _) => fields
+                .iter()
+                .for_each(|(_, p)| p.collect_bindings(bindings)),
+            Pattern::Wildcard | Pattern::Literal(_) => {}
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct CatchClause {
+    pub name: Variable,
+    pub body: Box<BlockStatement>,
+    pub locals: Locals,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Parameter {
+    pub name: Symbol,
+    /// The pattern a destructured parameter like `[a, b]` unpacks its
+    /// argument with
+    pub pattern: Option<Pattern>,
+    pub default: Option<Expression>,
+    pub variadic: bool,
+}
+
+impl Parameter {
+    /// Whether calling the function binds the variable `name`
+    pub fn binds(&self, name: Symbol) -> bool {
+        match &self.pattern {
+            Some(pattern) => pattern.bindings().iter().any(|v| v.name == name),
+            None => self.name == name,
+        }
+    }
+
+    /// The parameter as written: its name, or the pattern it destructures
+    pub fn label(&self) -> String {
+        match &self.pattern {
+            Some(pattern) => pattern.to_string(),
+            None => self.name.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Argument {
+    Positional(Expression),
+    Spread(Expression),
+    Named(Symbol, Expression),
+}
+
+///
+///  The statements between `{` and `}`. The blocks of `if`, `try` and block
+///  expressions are scopes of their own, whose variables the resolver lists
+///  in `locals`. The body of a function, catch clause or match arm belongs
+///  to the scope around it, so its `locals` stay empty
+///
 #[derive(Debug, PartialEq, Clone)]
 pub struct BlockStatement {
     pub stmts: Vec<Statement>,
+    pub locals: Locals,
 }
 
 pub struct Program {
@@ -41,6 +268,7 @@ pub struct Program {
 pub enum Prefix {
     Minus,
     Bang,
+    Tilde,
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -53,6 +281,14 @@ pub enum Infix {
     Minus,
     Asterisk,
     Slash,
+    BitAnd,
+    BitOr,
+    BitXor,
+    ShiftLeft,
+    ShiftRight,
+    NullCoalesce,
+    Range,
+    RangeInclusive,
     LBracket,
 }
 
@@ -76,11 +312,52 @@ impl fmt::Display for BlockStatement {
     }
 }
 
+impl fmt::Display for Variable {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "({}){}", join(&self.params), self.body)
+    }
+}
+
+impl fmt::Display for Parameter {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match (&self.default, self.variadic) {
+            (_, true) => write!(f, "...{}", self.name),
+            (Some(default), false) => write!(f, "{}={}", self.label(), default),
+            (None, false) => write!(f, "{}", self.label()),
+        }
+    }
+}
+
+impl fmt::Display for Argument {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Argument::Positional(exp) => write!(f, "{}", exp),
+            Argument::Spread(exp) => write!(f, "...{}", exp),
+            Argument::Named(name, exp) => write!(f, "{}: {}", name, exp),
+        }
+    }
+}
+
+pub fn join<T: fmt::Display>(items: &[T]) -> String {
+    items
+        .iter()
+        .map(|a| a.to_string())
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
 impl fmt::Display for Prefix {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         match self {
             Prefix::Minus => write!(f, "-"),
             Prefix::Bang => write!(f, "!"),
+            Prefix::Tilde => write!(f, "~"),
         }
     }
 }
@@ -96,6 +373,14 @@ impl fmt::Display for Infix {
             Infix::Minus => write!(f, "-"),
             Infix::Asterisk => write!(f, "*"),
             Infix::Slash => write!(f, "/"),
+            Infix::BitAnd => write!(f, "&"),
+            Infix::BitOr => write!(f, "|"),
+            Infix::BitXor => write!(f, "^"),
+            Infix::ShiftLeft => write!(f, "<<"),
+            Infix::ShiftRight => write!(f, ">>"),
+            Infix::NullCoalesce => write!(f, "??"),
+            Infix::Range => write!(f, ".."),
+            Infix::RangeInclusive => write!(f, "..="),
             Infix::LBracket => write!(f, "["),
         }
     }
@@ -104,31 +389,125 @@ impl fmt::Display for Infix {
 impl fmt::Display for Statement {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         match self {
-            Statement::Let(s, exp) => write!(f, "let {} = {};", s, exp),
+            Statement::Let(pattern, exp, _) => write!(f, "let {} = {};", pattern, exp),
+            Statement::LetMut(pattern, exp, _) => write!(f, "let mut {} = {};", pattern, exp),
+            Statement::Const(pattern, exp, _) => write!(f, "const {} = {};", pattern, exp),
             Statement::Return(None) => write!(f, "return;"),
             Statement::Return(Some(val)) => write!(f, "return {};", val),
+            Statement::Throw(val, _) => write!(f, "throw {};", val),
+            Statement::Function(name, function) => write!(f, "fn {}{}", name, function),
+            Statement::Struct(_, def) => write!(f, "{};", def),
+            Statement::Enum(_, def) => write!(f, "{};", def),
             Statement::Expression(exp) => write!(f, "{};", exp),
         }
     }
 }
 
+impl fmt::Display for StructDef {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let mut members: Vec<String> = self.fields.iter().map(|f| f.to_string()).collect();
+        for (name, function) in &self.methods {
+            members.push(format!("fn {}{}", name, function));
+        }
+        write!(f, "struct {} {{ {} }}", self.name, join(&members))
+    }
+}
+
+impl fmt::Display for EnumDef {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let variants: Vec<String> = self
+            .variants
+            .iter()
+            .map(|(name, fields)| match fields.is_empty() {
+                true => name.to_string(),
+                false => format!("{}({})", name, join(fields)),
+            })
+            .collect();
+        write!(f, "enum {} {{ {} }}", self.name, join(&variants))
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Pattern::Wildcard => write!(f, "_"),
+            Pattern::Binding(variable) => write!(f, "{}", variable),
+            Pattern::Literal(literal) => write!(f, "{}", literal),
+            Pattern::Array(items, rest) => {
+                let mut items: Vec<String> = items.iter().map(|p| p.to_string()).collect();
+                if let Some(rest) = rest {
+                    items.push(format!("...{}", rest));
+                }
+                write!(f, "[{}]", join(&items))
+            }
+            Pattern::Tuple(items) if items.len() == 1 => write!(f, "({},)", items[0]),
+            Pattern::Tuple(items) => write!(f, "({})", join(items)),
+            Pattern::HashMap(entries) => {
+                let entries: Vec<String> = entries
+                    .iter()
+                    .map(|(key, pattern)| format!("{}:{}", key, pattern))
+                    .collect();
+                write!(f, "{{{}}}", join(&entries))
+            }
+            Pattern::Struct(name, fields, _) => {
+                let fields: Vec<String> = fields
+                    .iter()
+                    .map(|(field, pattern)| format!("{}: {}", field, pattern))
+                    .collect();
+                write!(f, "{} {{ {} }}", name, join(&fields))
+            }
+            Pattern::Variant(name, variant, items, _) if items.is_empty() => {
+                write!(f, "{}.{}", name, variant)
+            }
+            Pattern::Variant(name, variant, items, _) => {
+                write!(f, "{}.{}({})", name, variant, join(items))
+            }
+        }
+    }
+}
+
+impl fmt::Display for MatchArm {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.pattern)?;
+        if let Some(guard) = &self.guard {
+            write!(f, " if {}", guard)?;
+        }
+        write!(f, " => {}", self.body)
+    }
+}
+
 impl fmt::Display for Expression {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         match self {
-            Expression::Identifier(s) => write!(f, "{}", s),
+            Expression::Identifier(s, _) => write!(f, "{}", s),
             Expression::IntegerLiteral(i) => write!(f, "{}", i),
             Expression::String(s) => write!(f, "\"{}\"", s),
             Expression::Bool(b) => write!(f, "{}", b),
-            Expression::Prefix(p, exp) => write!(f, "({}, {})", p, exp),
-            Expression::Infix(op, left, right) => write!(f, "({} {} {})", op, left, right),
-            Expression::If(exp, true_blk, Some(false_blk)) => {
-                write!(f, "if ({}) {} else {}", exp, true_blk, false_blk)
+            Expression::Null => write!(f, "null"),
+            Expression::Prefix(p, exp, _) => write!(f, "({}, {})", p, exp),
+            Expression::Infix(op, left, right, _) => write!(f, "({} {} {})", op, left, right),
+            Expression::Assign(name, value, _) => write!(f, "(= {} {})", name, value),
+            Expression::IndexAssign(container, idx, value, _) => {
+                write!(f, "(= {}[{}] {})", container, idx, value)
+            }
+            Expression::If(branches, false_blk) => {
+                let branches: Vec<String> = branches
+                    .iter()
+                    .map(|(cond, _, blk)| format!("if ({}) {}", cond, blk))
+                    .collect();
+                write!(f, "{}", branches.join(" else "))?;
+                if let Some(false_blk) = false_blk {
+                    write!(f, " else {}", false_blk)?;
+                }
+                Ok(())
+            }
+            Expression::Ternary(cond, true_expr, false_expr, _) => {
+                write!(f, "(? {} {} {})", cond, true_expr, false_expr)
             }
-            Expression::If(exp, true_blk, None) => write!(f, "if ({}) {}", exp, true_blk),
             Expression::HashMapLiteral(key_values) => {
                 let mut str = String::new();
                 str.push('{');
-                for (k, v) in key_values {
+                for (k, v, _) in key_values {
                     str.push_str(format!("{}:{},", k, v).as_str());
                 }
 
@@ -138,29 +517,55 @@ impl fmt::Display for Expression {
                 str.push('}');
                 write!(f, "{}", str)
             }
-            Expression::ArrayLiteral(members) => write!(
-                f,
-                "[{}]",
-                members
-                    .iter()
-                    .map(|a| a.to_string())
-                    .collect::<Vec<String>>()
-                    .join(",")
-            ),
-            Expression::Index(arr, idx) => write!(f, "{}[{}]", arr, idx),
-            Expression::FunctionLiteral(params, block) => {
-                write!(f, "fn({}){}", params.join(","), block)
-            }
-            Expression::Call(exp, params) => write!(
-                f,
-                "{}({})",
-                exp,
-                params
+            Expression::ArrayLiteral(members) => write!(f, "[{}]", join(members)),
+            Expression::TupleLiteral(members) if members.len() == 1 => {
+                write!(f, "({},)", members[0])
+            }
+            Expression::TupleLiteral(members) => write!(f, "({})", join(members)),
+            Expression::Index(arr, idx, _) => write!(f, "{}[{}]", arr, idx),
+            Expression::OptionalIndex(arr, idx, _) => write!(f, "{}?[{}]", arr, idx),
+            Expression::Field(object, field, _) => write!(f, "{}.{}", object, field),
+            Expression::OptionalField(object, field, _) => write!(f, "{}?.{}", object, field),
+            Expression::FieldAssign(object, field, value, _) => {
+                write!(f, "(= {}.{} {})", object, field, value)
+            }
+            Expression::StructLiteral(name, fields, _) => {
+                let fields: Vec<String> = fields
                     .iter()
-                    .map(|a| a.to_string())
-                    .collect::<Vec<String>>()
-                    .join(",")
-            ),
+                    .map(|(field, value)| format!("{}: {}", field, value))
+                    .collect();
+                write!(f, "{} {{ {} }}", name, join(&fields))
+            }
+            Expression::Slice(start, end) => {
+                if let Some(start) = start {
+                    write!(f, "{}", start)?;
+                }
+                write!(f, ":")?;
+                if let Some(end) = end {
+                    write!(f, "{}", end)?;
+                }
+                Ok(())
+            }
+            Expression::FunctionLiteral(function) => write!(f, "fn{}", function),
+            Expression::Call(exp, args, _) => write!(f, "{}({})", exp, join(args)),
+            Expression::MethodCall(receiver, method, args, _) => {
+                write!(f, "{}.{}({})", receiver, method, join(args))
+            }
+            Expression::OptionalCall(exp, args, _) => write!(f, "{}?.({})", exp, join(args)),
+            Expression::Try(body, catch, finally) => {
+                write!(f, "try {}", body)?;
+                if let Some(catch) = catch {
+                    write!(f, " catch ({}) {}", catch.name, catch.body)?;
+                }
+                if let Some(finally_blk) = finally {
+                    write!(f, " finally {}", finally_blk)?;
+                }
+                Ok(())
+            }
+            Expression::Match(subject, arms, _) => {
+                write!(f, "match ({}) {{ {} }}", subject, join(arms))
+            }
+            Expression::Block(block) => write!(f, "{}", block),
         }
     }
 }
@@ -1,11 +1,24 @@
+use crate::ast::{Binding, Variable};
 use crate::object::Object;
+use crate::symbol::Symbol;
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::rc::Rc;
 
+///
+///  A scope. The global scope keeps its variables by name, function calls
+///  and catch clauses get a local scope whose variables live in the slots
+///  the resolver assigned them
+///
 #[derive(Debug, Default, PartialEq)]
 pub struct EnviromentVariables {
-    store: HashMap<String, Object>,
+    store: HashMap<Symbol, Object>,
+    /// The globals declared with `const`
+    constants: HashSet<Symbol>,
+    /// The globals last declared with a `let` without `mut`
+    immutables: HashSet<Symbol>,
+    locals: Rc<[Symbol]>,
+    slots: Vec<Option<Object>>,
     outer: Option<Rc<RefCell<EnviromentVariables>>>,
 }
 
@@ -14,24 +27,178 @@ impl EnviromentVariables {
         Default::default()
     }
 
-    pub fn extend(outer: Rc<RefCell<Self>>) -> Self {
+    pub fn extend(outer: Rc<RefCell<Self>>, locals: Rc<[Symbol]>) -> Self {
         Self {
             store: HashMap::new(),
+            constants: HashSet::new(),
+            immutables: HashSet::new(),
+            slots: vec![None; locals.len()],
+            locals,
             outer: Some(outer),
         }
     }
 
-    pub fn get(&self, name: &str) -> Option<Object> {
-        match self.store.get(name) {
-            Some(value) => Some(value.clone()),
-            None => self
-                .outer
-                .as_ref()
-                .and_then(|o| o.borrow().get(name).clone()),
+    ///
+    ///  Returns `None` for a global that is not defined, or a local whose
+    ///  definition has not run yet
+    ///
+    pub fn get(&self, variable: &Variable) -> Option<Object> {
+        match variable.binding.get() {
+            Binding::Global => self.get_global(variable.name),
+            Binding::Local { depth, slot } => self.get_local(depth, slot),
         }
     }
 
-    pub fn set(&mut self, name: &str, value: Object) {
-        self.store.insert(name.to_string(), value);
+    fn get_global(&self, name: Symbol) -> Option<Object> {
+        match &self.outer {
+            Some(outer) => outer.borrow().get_global(name),
+            None => self.store.get(&name).cloned(),
+        }
+    }
+
+    fn get_local(&self, depth: usize, slot: usize) -> Option<Object> {
+        match (depth, &self.outer) {
+            (0, _) => self.slots[slot].clone(),
+            (_, Some(outer)) => outer.borrow().get_local(depth - 1, slot),
+            (_, None) => panic!("Local variable resolved past the global scope"),
+        }
+    }
+
+    ///
+    ///  Binds `variable` in the scope the resolver placed it in, as `let`,
+    ///  function declarations, parameters and catch clauses do
+    ///
+    pub fn define(&mut self, variable: &Variable, value: Object) {
+        match variable.binding.get() {
+            Binding::Global => self.define_global(variable.name, value),
+            Binding::Local { depth, slot } => self.set_local(depth, slot, value),
+        }
+    }
+
+    ///
+    ///  Binds `variable` like `define`, and for a global also records that
+    ///  it can't be assigned. The resolver already rejects assigning a local
+    ///  one
+    ///
+    pub fn define_immutable(&mut self, variable: &Variable, value: Object) {
+        self.define(variable, value);
+        if variable.binding.get() == Binding::Global {
+            self.add_global_immutable(variable.name);
+        }
+    }
+
+    fn add_global_immutable(&mut self, name: Symbol) {
+        match &self.outer {
+            Some(outer) => outer.borrow_mut().add_global_immutable(name),
+            None => {
+                self.immutables.insert(name);
+            }
+        }
+    }
+
+    /// Whether `variable` is a global last declared with a `let` without `mut`
+    pub fn is_immutable(&self, variable: &Variable) -> bool {
+        variable.binding.get() == Binding::Global && self.is_global_immutable(variable.name)
+    }
+
+    fn is_global_immutable(&self, name: Symbol) -> bool {
+        match &self.outer {
+            Some(outer) => outer.borrow().is_global_immutable(name),
+            None => self.immutables.contains(&name),
+        }
+    }
+
+    ///
+    ///  Binds `variable` like `define`, and for a global also records that
+    ///  it is a constant. The resolver already rejects assigning a local one
+    ///
+    pub fn define_constant(&mut self, variable: &Variable, value: Object) {
+        self.define(variable, value);
+        if variable.binding.get() == Binding::Global {
+            self.add_global_constant(variable.name);
+        }
+    }
+
+    fn add_global_constant(&mut self, name: Symbol) {
+        match &self.outer {
+            Some(outer) => outer.borrow_mut().add_global_constant(name),
+            None => {
+                self.constants.insert(name);
+            }
+        }
+    }
+
+    /// Whether `variable` is a global declared with `const`
+    pub fn is_constant(&self, variable: &Variable) -> bool {
+        variable.binding.get() == Binding::Global && self.is_global_constant(variable.name)
+    }
+
+    fn is_global_constant(&self, name: Symbol) -> bool {
+        match &self.outer {
+            Some(outer) => outer.borrow().is_global_constant(name),
+            None => self.constants.contains(&name),
+        }
+    }
+
+    fn define_global(&mut self, name: Symbol, value: Object) {
+        match &self.outer {
+            Some(outer) => outer.borrow_mut().define_global(name, value),
+            None => {
+                self.immutables.remove(&name);
+                self.store.insert(name, value);
+            }
+        }
+    }
+
+    pub fn set_local(&mut self, depth: usize, slot: usize, value: Object) {
+        match (depth, &self.outer) {
+            (0, _) => self.slots[slot] = Some(value),
+            (_, Some(outer)) => outer.borrow_mut().set_local(depth - 1, slot, value),
+            (_, None) => panic!("Local variable resolved past the global scope"),
+        }
+    }
+
+    ///
+    ///  Rebinds an already defined `variable`, so closures update the
+    ///  variables they captured instead of shadowing them. Returns `false`
+    ///  when it is not defined
+    ///
+    pub fn assign(&mut self, variable: &Variable, value: Object) -> bool {
+        if self.get(variable).is_none() {
+            return false;
+        }
+        self.define(variable, value);
+        true
+    }
+
+    /// The names of every variable visible from this scope
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.store.keys().map(|name| name.to_string()).collect();
+        names.extend(self.locals.iter().map(|name| name.to_string()));
+        if let Some(outer) = &self.outer {
+            names.extend(outer.borrow().names());
+        }
+        names
+    }
+
+    ///
+    ///  Calls `f` with every heap object this scope keeps alive: its outer
+    ///  scope and whatever its variables refer to
+    ///
+    pub fn for_each_reference(&self, f: &mut dyn FnMut(*const ())) {
+        if let Some(outer) = &self.outer {
+            f(Rc::as_ptr(outer) as *const ());
+        }
+        for value in self.store.values().chain(self.slots.iter().flatten()) {
+            value.for_each_reference(f);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.store.clear();
+        self.constants.clear();
+        self.immutables.clear();
+        self.slots.clear();
+        self.outer = None;
     }
 }
@@ -1,345 +1,1717 @@
 use crate::ast::*;
 use crate::enviroment::EnviromentVariables;
-use crate::inbuilt::{eval_builtin, get_builtin};
-use crate::object::Object;
+use crate::gc;
+use crate::inbuilt::{eval_builtin, get_builtin, get_method, BUILTINS};
+use crate::lexer::Position;
+use crate::object::{range_len, HashKey, Object, StructRef};
+use crate::symbol::Symbol;
+use indexmap::IndexMap;
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::fmt;
 use std::rc::Rc;
 
-pub fn eval_identifier(identifier: &Expression, env: &Rc<RefCell<EnviromentVariables>>) -> Object {
-    match identifier {
-        Expression::Identifier(i) => {
-            let id = env.borrow_mut().get(i.as_str());
-            if let Some(id) = id {
-                return id;
+pub const ERROR: &str = "Error";
+pub const NAME_ERROR: &str = "NameError";
+pub const TYPE_ERROR: &str = "TypeError";
+pub const INDEX_ERROR: &str = "IndexError";
+pub const ARGUMENT_ERROR: &str = "ArgumentError";
+pub const ZERO_DIVISION_ERROR: &str = "ZeroDivisionError";
+pub const OVERFLOW_ERROR: &str = "OverflowError";
+pub const RECURSION_ERROR: &str = "RecursionError";
+pub const ATTRIBUTE_ERROR: &str = "AttributeError";
+pub const MATCH_ERROR: &str = "MatchError";
+
+/// Deepest chain of nested user function calls before a `RecursionError` is raised
+pub const MAX_CALL_DEPTH: usize = 1000;
+
+/// Consecutive identical frames beyond this count are collapsed in tracebacks
+const TRACEBACK_REPEAT_LIMIT: usize = 3;
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Frame {
+    pub function: String,
+    pub position: Position,
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at {}", self.function, self.position)
+    }
+}
+
+thread_local! {
+    static CALL_STACK: RefCell<Vec<Frame>> = const { RefCell::new(vec![]) };
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct RuntimeError {
+    pub kind: String,
+    pub message: String,
+    pub position: Option<Position>,
+    pub trace: Vec<Frame>,
+    /// The value a `throw` raised, which a catch clause receives unchanged
+    pub value: Option<Box<Object>>,
+}
+
+impl RuntimeError {
+    /// Creates an error whose trace is the call stack at the point of creation
+    pub fn new(kind: &str, message: String) -> Self {
+        Self {
+            kind: kind.to_string(),
+            message,
+            position: None,
+            trace: CALL_STACK.with(|stack| stack.borrow().clone()),
+            value: None,
+        }
+    }
+
+    ///
+    ///  The error `throw value` raises for a value that is not an error. Its
+    ///  message, shown when nothing catches it, is the value itself
+    ///
+    pub fn thrown(value: Object) -> Self {
+        let message = match &value {
+            Object::String(message) => message.clone(),
+            value => value.to_string(),
+        };
+        Self {
+            value: Some(Box::new(value)),
+            ..Self::new(ERROR, message)
+        }
+    }
+
+    ///
+    ///  The fields a caught error reads as, like a hashmap: its `message`,
+    ///  `kind` and `trace`
+    ///
+    pub fn fields(&self) -> IndexMap<HashKey, Object> {
+        let trace = self
+            .trace
+            .iter()
+            .map(|frame| Object::String(frame.to_string()))
+            .collect();
+        IndexMap::from([
+            (
+                HashKey::String(String::from("message")),
+                Object::String(self.message.clone()),
+            ),
+            (
+                HashKey::String(String::from("kind")),
+                Object::String(self.kind.clone()),
+            ),
+            (HashKey::String(String::from("trace")), Object::array(trace)),
+        ])
+    }
+
+    pub fn at(mut self, position: Position) -> Self {
+        self.position = Some(position);
+        self
+    }
+
+    fn or_at(self, position: Position) -> Self {
+        match self.position {
+            Some(_) => self,
+            None => self.at(position),
+        }
+    }
+}
+
+impl RuntimeError {
+    ///
+    ///  Formats the error with its call stack, outermost call first, the way the
+    ///  REPL and the CLI report uncaught errors
+    ///
+    pub fn traceback(&self) -> String {
+        let mut out = String::new();
+        if !self.trace.is_empty() {
+            out.push_str("Traceback (most recent call last):\n");
+        }
+
+        let mut idx = 0;
+        while idx < self.trace.len() {
+            let frame = &self.trace[idx];
+            let repeated = self.trace[idx..]
+                .iter()
+                .take_while(|other| *other == frame)
+                .count();
+
+            for _ in 0..repeated.min(TRACEBACK_REPEAT_LIMIT) {
+                out.push_str(&format!("  in {}\n", frame));
+            }
+            if repeated > TRACEBACK_REPEAT_LIMIT {
+                out.push_str(&format!(
+                    "  [previous frame repeated {} more times]\n",
+                    repeated - TRACEBACK_REPEAT_LIMIT
+                ));
             }
+            idx += repeated;
+        }
+
+        out.push_str(&self.to_string());
+        out
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.position {
+            Some(pos) => write!(f, "{} at {}: {}", self.kind, pos, self.message),
+            None => write!(f, "{}: {}", self.kind, self.message),
+        }
+    }
+}
+
+pub type EvalResult = Result<Object, RuntimeError>;
+
+///
+///  Optimal string alignment distance, a Levenshtein distance that also counts
+///  swapping two adjacent characters as a single edit
+///
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
 
-            let inbuilt_func = get_builtin(i.as_str());
-            if let Some(inbuilt_func) = inbuilt_func {
-                return inbuilt_func;
+    d[0] = (0..=b.len()).collect();
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            d[i][j] = (d[i - 1][j] + 1)
+                .min(d[i][j - 1] + 1)
+                .min(d[i - 1][j - 1] + cost);
+
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
             }
         }
+    }
+
+    d[a.len()][b.len()]
+}
+
+///
+///  Finds the closest visible name to a misspelled identifier, allowing roughly
+///  one edit for every three characters of the name
+///
+fn suggest_identifier(name: &str, env: &Rc<RefCell<EnviromentVariables>>) -> Option<String> {
+    let max_distance = (name.chars().count() / 3).max(1);
+    let mut candidates = env.borrow().names();
+    candidates.extend(BUILTINS.iter().map(|b| b.to_string()));
+    candidates.sort();
+    candidates.dedup();
+
+    candidates
+        .into_iter()
+        .map(|c| (edit_distance(name, &c), c))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, c)| c)
+}
+
+pub fn eval_identifier(
+    identifier: &Expression,
+    env: &Rc<RefCell<EnviromentVariables>>,
+) -> EvalResult {
+    match identifier {
+        Expression::Identifier(variable, position) => eval_variable(variable, *position, env),
         _ => panic!("Expected identifier"),
     }
+}
+
+fn eval_variable(
+    variable: &Variable,
+    position: Position,
+    env: &Rc<RefCell<EnviromentVariables>>,
+) -> EvalResult {
+    let id = env.borrow().get(variable);
+    if let Some(id) = id {
+        return Ok(id);
+    }
+
+    if variable.binding.get() == Binding::Global {
+        let inbuilt_func = get_builtin(&variable.name.name());
+        if let Some(inbuilt_func) = inbuilt_func {
+            return Ok(inbuilt_func);
+        }
+    }
+
+    Err(undefined_variable(variable, position, env))
+}
+
+fn undefined_variable(
+    variable: &Variable,
+    position: Position,
+    env: &Rc<RefCell<EnviromentVariables>>,
+) -> RuntimeError {
+    if let Binding::Local { .. } = variable.binding.get() {
+        return RuntimeError::new(
+            NAME_ERROR,
+            format!("variable `{}` used before its definition", variable),
+        )
+        .at(position);
+    }
+
+    let name = variable.name.name();
+    let mut message = format!("undefined variable `{}`", name);
+    if let Some(suggestion) = suggest_identifier(&name, env) {
+        message.push_str(&format!(", did you mean `{}`?", suggestion));
+    }
+    RuntimeError::new(NAME_ERROR, message).at(position)
+}
 
-    Object::Null
+pub fn immutable_assignment(variable: &Variable) -> RuntimeError {
+    RuntimeError::new(
+        TYPE_ERROR,
+        format!(
+            "cannot assign to immutable variable `{}`, declare it with `let mut`",
+            variable
+        ),
+    )
+}
+
+pub fn eval_assign_expression(
+    variable: &Variable,
+    expr: &Expression,
+    position: Position,
+    env: &mut Rc<RefCell<EnviromentVariables>>,
+) -> EvalResult {
+    let value = eval_expression(expr, env)?;
+    if env.borrow().is_constant(variable) {
+        return Err(RuntimeError::new(
+            TYPE_ERROR,
+            format!("cannot assign to constant `{}`", variable),
+        )
+        .at(position));
+    }
+    if env.borrow().is_immutable(variable) {
+        return Err(immutable_assignment(variable).at(position));
+    }
+    if env.borrow_mut().assign(variable, value.clone()) {
+        Ok(value)
+    } else {
+        Err(undefined_variable(variable, position, env))
+    }
 }
 
 pub fn eval_infix_expression(
     infix: &Infix,
     left: &Expression,
     right: &Expression,
+    position: Position,
     env: &mut Rc<RefCell<EnviromentVariables>>,
-) -> Object {
-
-    let left_obj = eval_expression(left, env);
-    let right_obj = eval_expression(right, env);
-
-    match left_obj {
-        Object::Integer(i) => {
-            let left_int = i;
-            let right_int;
-            match right_obj {
-                Object::Integer(i) => {
-                    right_int = i;
-                    match infix {
-                        Infix::Plus => Object::Integer(left_int + right_int),
-                        Infix::Minus => Object::Integer(left_int - right_int),
-                        Infix::Asterisk => Object::Integer(left_int * right_int),
-                        Infix::Slash => Object::Integer(left_int / right_int),
-                        Infix::NotEq => Object::Bool(left_int != right_int),
-                        Infix::Eq => Object::Bool(left_int == right_int),
-                        Infix::Gt => Object::Bool(left_int > right_int),
-                        Infix::Lt => Object::Bool(left_int < right_int),
-                        _ => panic!("Inintid op {}", infix),
-                    }
-                }
-                _ => panic!("Expected integer {}", right_obj),
+) -> EvalResult {
+    let left_obj = eval_expression(left, env)?;
+    if *infix == Infix::NullCoalesce {
+        return match left_obj {
+            Object::Null => eval_expression(right, env),
+            _ => Ok(left_obj),
+        };
+    }
+
+    let right_obj = eval_expression(right, env)?;
+    eval_infix_objects(infix, left_obj, right_obj).map_err(|e| e.at(position))
+}
+
+pub fn eval_infix_objects(infix: &Infix, left_obj: Object, right_obj: Object) -> EvalResult {
+    if left_obj == Object::Null || right_obj == Object::Null {
+        return match infix {
+            Infix::Eq => Ok(Object::Bool(left_obj == right_obj)),
+            Infix::NotEq => Ok(Object::Bool(left_obj != right_obj)),
+            _ => Err(invalid_operands(infix, &left_obj, &right_obj)),
+        };
+    }
+
+    match (&left_obj, &right_obj) {
+        (Object::Integer(left_int), Object::Integer(right_int)) => {
+            eval_integer_infix_expression(infix, *left_int, *right_int)
+        }
+        (Object::String(left_str), Object::String(right_str)) => match infix {
+            Infix::Plus => Ok(Object::String(left_str.to_owned() + right_str)),
+            _ => Err(invalid_operands(infix, &left_obj, &right_obj)),
+        },
+        (left, right)
+            if matches!(infix, Infix::ShiftRight) && left.is_callable() && right.is_callable() =>
+        {
+            Ok(Object::Composition(Box::new(left_obj), Box::new(right_obj)))
+        }
+        _ => Err(invalid_operands(infix, &left_obj, &right_obj)),
+    }
+}
+
+fn invalid_operands(infix: &Infix, left: &Object, right: &Object) -> RuntimeError {
+    RuntimeError::new(
+        TYPE_ERROR,
+        format!(
+            "unsupported operand types for {}: {} and {}",
+            infix,
+            left.type_name(),
+            right.type_name()
+        ),
+    )
+}
+
+pub fn eval_integer_infix_expression(infix: &Infix, left_int: i64, right_int: i64) -> EvalResult {
+    let overflow = || {
+        RuntimeError::new(
+            OVERFLOW_ERROR,
+            format!("integer overflow in {} {} {}", left_int, infix, right_int),
+        )
+    };
+
+    let result = match infix {
+        Infix::Plus => Object::Integer(left_int.checked_add(right_int).ok_or_else(overflow)?),
+        Infix::Minus => Object::Integer(left_int.checked_sub(right_int).ok_or_else(overflow)?),
+        Infix::Asterisk => Object::Integer(left_int.checked_mul(right_int).ok_or_else(overflow)?),
+        Infix::Slash => {
+            if right_int == 0 {
+                return Err(RuntimeError::new(
+                    ZERO_DIVISION_ERROR,
+                    format!("division by zero in {} / 0", left_int),
+                ));
             }
+            Object::Integer(left_int.checked_div(right_int).ok_or_else(overflow)?)
         }
-        Object::String(s) => {
-            let left_str = s;
-            let right_str;
-            match right_obj {
-                Object::String(s) => {
-                    right_str = s;
-                    match infix {
-                        Infix::Plus => Object::String(left_str + &*right_str),
-                        _ => panic!("Invalid op {} for strings", infix),
-                    }
-                }
-                _ => panic!("Expected String{}", right_obj),
+        Infix::NotEq => Object::Bool(left_int != right_int),
+        Infix::Eq => Object::Bool(left_int == right_int),
+        Infix::Gt => Object::Bool(left_int > right_int),
+        Infix::Lt => Object::Bool(left_int < right_int),
+        Infix::BitAnd => Object::Integer(left_int & right_int),
+        Infix::BitOr => Object::Integer(left_int | right_int),
+        Infix::BitXor => Object::Integer(left_int ^ right_int),
+        Infix::Range => Object::Range(left_int, right_int),
+        Infix::RangeInclusive => {
+            Object::Range(left_int, right_int.checked_add(1).ok_or_else(overflow)?)
+        }
+        Infix::ShiftLeft | Infix::ShiftRight => {
+            if !(0..64).contains(&right_int) {
+                return Err(RuntimeError::new(
+                    OVERFLOW_ERROR,
+                    format!("invalid shift amount {}, expected 0 to 63", right_int),
+                ));
+            }
+
+            match infix {
+                Infix::ShiftLeft => Object::Integer(left_int << right_int),
+                _ => Object::Integer(left_int >> right_int),
             }
         }
-        _ => panic!("Invalid value in expression {}, expected int", left_obj),
-    }
+        _ => {
+            return Err(invalid_operands(
+                infix,
+                &Object::Integer(left_int),
+                &Object::Integer(right_int),
+            ))
+        }
+    };
+
+    Ok(result)
 }
 
 pub fn eval_prefix_expression(
     prefix: &Prefix,
     expression: &Expression,
+    position: Position,
     env: &mut Rc<RefCell<EnviromentVariables>>,
-) -> Object {
-    let expr_val = eval_expression(expression, env);
-    match prefix {
-        Prefix::Minus => match expr_val {
-            Object::Integer(i) => Object::Integer(-i),
-            _ => panic!(
-                "Invalid expression {} in prefix expression, expected integer",
-                expr_val
-            ),
-        },
-        Prefix::Bang => match expr_val {
-            Object::Bool(b) => Object::Bool(!b),
-            _ => panic!(
-                "Invalid expression {} in prefix expression, expected boolean",
-                expr_val
+) -> EvalResult {
+    let expr_val = eval_expression(expression, env)?;
+    let result = match (prefix, &expr_val) {
+        (Prefix::Minus, Object::Integer(i)) => {
+            i.checked_neg().map(Object::Integer).ok_or_else(|| {
+                RuntimeError::new(OVERFLOW_ERROR, format!("integer overflow in -{}", i))
+            })
+        }
+        (Prefix::Bang, Object::Bool(b)) => Ok(Object::Bool(!b)),
+        (Prefix::Tilde, Object::Integer(i)) => Ok(Object::Integer(!i)),
+        _ => Err(RuntimeError::new(
+            TYPE_ERROR,
+            format!(
+                "unsupported operand type for {}: {}",
+                prefix,
+                expr_val.type_name()
             ),
-        },
+        )),
+    };
+
+    result.map_err(|e| e.at(position))
+}
+
+///
+///  Binds every `fn name(..) {..}`, `struct Name {..}` and `enum Name {..}`
+///  declaration of a scope before any of its statements run, so declarations
+///  can be used, and use each other, from anywhere in that scope
+///
+pub fn hoist_declarations(stmts: &[Statement], env: &mut Rc<RefCell<EnviromentVariables>>) {
+    for stmt in stmts {
+        match stmt {
+            Statement::Function(variable, function) => {
+                let func = Object::FunctionLiteral(
+                    Some(variable.to_string()),
+                    function.clone(),
+                    env.clone(),
+                );
+                env.borrow_mut().define(variable, func);
+            }
+            Statement::Struct(variable, def) => {
+                env.borrow_mut()
+                    .define(variable, Object::StructType(def.clone(), env.clone()));
+            }
+            Statement::Enum(variable, def) => {
+                env.borrow_mut()
+                    .define(variable, Object::EnumType(def.clone()));
+            }
+            _ => {}
+        }
     }
 }
 
 pub fn eval_block_statement(
     block: &BlockStatement,
     env: &mut Rc<RefCell<EnviromentVariables>>,
-) -> Object {
+) -> EvalResult {
+    hoist_declarations(&block.stmts, env);
 
     let mut val = Object::Null;
     for stmt in &block.stmts {
         val = match stmt {
-            Statement::Let(x, expr) => eval_let_statement(x.to_string(), expr, env),
+            Statement::Let(pattern, expr, position) => {
+                let define = EnviromentVariables::define_immutable;
+                eval_let_statement(pattern, expr, define, *position, env)?
+            }
+            Statement::LetMut(pattern, expr, position) => {
+                let define = EnviromentVariables::define;
+                eval_let_statement(pattern, expr, define, *position, env)?
+            }
+            Statement::Const(pattern, expr, position) => {
+                let define = EnviromentVariables::define_constant;
+                eval_let_statement(pattern, expr, define, *position, env)?
+            }
             Statement::Return(Some(x)) => {
                 return eval_return_statement(x, env);
             }
             Statement::Return(None) => {
-                return Object::Null;
+                return Ok(Object::Null);
             }
-            Statement::Expression(expr) => eval_expression(expr, env),
+            Statement::Throw(expr, position) => return eval_throw_statement(expr, *position, env),
+            Statement::Function(..) | Statement::Struct(..) | Statement::Enum(..) => Object::Null,
+            Statement::Expression(expr) => eval_expression(expr, env)?,
         }
     }
-    val
+    Ok(val)
+}
+
+///
+///  Runs a block that is a scope of its own, in a new scope extending `env`
+///  when it declares variables
+///
+pub fn eval_scoped_block(
+    block: &BlockStatement,
+    env: &mut Rc<RefCell<EnviromentVariables>>,
+) -> EvalResult {
+    let locals = block.locals.borrow().clone();
+    if locals.is_empty() {
+        return eval_block_statement(block, env);
+    }
+
+    let mut block_env = gc::allocate(EnviromentVariables::extend(env.clone(), locals));
+    eval_block_statement(block, &mut block_env)
 }
 
+///
+///  Runs the block of the first branch whose condition holds, or the `else`
+///  block when none does
+///
 pub fn eval_if_expression(
-    expr: &Expression,
-    true_block: &BlockStatement,
+    branches: &[(Expression, Position, BlockStatement)],
     false_block: &Option<Box<BlockStatement>>,
     env: &mut Rc<RefCell<EnviromentVariables>>,
-) -> Object {
-
-    let expr_obj = eval_expression(expr, env);
-    let expr_val = match expr_obj {
-        Object::Bool(v) => v,
-        _ => panic!(
-            "Expected boolean expression in if statement, found {}",
-            expr_obj
-        ),
-    };
+) -> EvalResult {
+    for (expr, position, block) in branches {
+        match eval_expression(expr, env)? {
+            Object::Bool(true) => return eval_scoped_block(block, env),
+            Object::Bool(false) => {}
+            expr_obj => {
+                return Err(RuntimeError::new(
+                    TYPE_ERROR,
+                    format!(
+                        "expected boolean condition in if expression, found {}",
+                        expr_obj.type_name()
+                    ),
+                )
+                .at(*position))
+            }
+        }
+    }
 
-    if expr_val {
-        eval_block_statement(true_block, env)
-    } else if false_block.is_some() {
-        eval_block_statement(false_block.as_ref().unwrap().as_ref(), env)
-    } else {
-        Object::Null
+    match false_block {
+        Some(false_block) => eval_scoped_block(false_block, env),
+        None => Ok(Object::Null),
     }
 }
 
-pub fn eval_user_defined_function_call(
-    func_params: &[String],
-    param_objs: &[Object],
-    func_block: &BlockStatement,
+pub fn eval_ternary_expression(
+    cond: &Expression,
+    true_expr: &Expression,
+    false_expr: &Expression,
+    position: Position,
     env: &mut Rc<RefCell<EnviromentVariables>>,
-) -> Object {
-
-    let mut func_new_env = Rc::new(RefCell::new(EnviromentVariables::extend(env.clone())));
-
-    let mut idx = 0;
-    while idx < param_objs.len() {
-        func_new_env
-            .borrow_mut()
-            .set(&func_params[idx], param_objs[idx].clone());
-        idx += 1;
+) -> EvalResult {
+    match eval_expression(cond, env)? {
+        Object::Bool(true) => eval_expression(true_expr, env),
+        Object::Bool(false) => eval_expression(false_expr, env),
+        cond_obj => Err(RuntimeError::new(
+            TYPE_ERROR,
+            format!(
+                "ternary condition must be a boolean, found {}",
+                cond_obj.type_name()
+            ),
+        )
+        .at(position)),
     }
-
-    eval_block_statement(func_block, &mut func_new_env)
 }
 
-pub fn eval_dict_literal(
-    dict_expr: &Vec<(Expression, Expression)>,
+///
+///  Runs the first arm whose pattern matches `subject` and whose guard, if
+///  any, is true, in a scope holding the variables its pattern bound
+///
+pub fn eval_match_expression(
+    subject_expr: &Expression,
+    arms: &[MatchArm],
+    position: Position,
     env: &mut Rc<RefCell<EnviromentVariables>>,
-) -> Object {
-    let mut dict = HashMap::new();
-    for (key_expr, val_expr) in dict_expr {
-        let key = eval_expression(key_expr, env);
-        let val = eval_expression(val_expr, env);
-        dict.insert(key, val);
+) -> EvalResult {
+    let subject = eval_expression(subject_expr, env)?;
+
+    for arm in arms {
+        let mut bindings = vec![];
+        if !match_pattern(&arm.pattern, &subject, &mut bindings, env)? {
+            continue;
+        }
+
+        let locals = arm.locals.borrow().clone();
+        let mut arm_env = gc::allocate(EnviromentVariables::extend(env.clone(), locals));
+        for (variable, value) in bindings {
+            arm_env.borrow_mut().define(variable, value);
+        }
+
+        if let Some(guard) = &arm.guard {
+            match eval_expression(guard, &mut arm_env)? {
+                Object::Bool(true) => {}
+                Object::Bool(false) => continue,
+                obj => {
+                    return Err(RuntimeError::new(
+                        TYPE_ERROR,
+                        format!(
+                            "expected boolean guard in match arm, found {}",
+                            obj.type_name()
+                        ),
+                    )
+                    .at(position))
+                }
+            }
+        }
+        return eval_block_statement(&arm.body, &mut arm_env);
     }
 
-    Object::HashMap(dict)
+    Err(RuntimeError::new(MATCH_ERROR, format!("no match arm matches {}", subject)).at(position))
 }
 
-pub fn eval_array_literal(
-    member_expr: &[Expression],
+///
+///  Whether `value` has the shape of `pattern`, pushing the values of the
+///  variables it binds onto `bindings` as it goes. The struct and enum names
+///  a pattern uses are looked up in `env`
+///
+fn match_pattern<'a>(
+    pattern: &'a Pattern,
+    value: &Object,
+    bindings: &mut Vec<(&'a Variable, Object)>,
     env: &mut Rc<RefCell<EnviromentVariables>>,
-) -> Object {
-    let mut members = vec![];
-    for mem in member_expr.iter() {
-        members.push(eval_expression(mem, env));
-    }
-    Object::Array(members)
-}
+) -> Result<bool, RuntimeError> {
+    match (pattern, value) {
+        (Pattern::Wildcard, _) => Ok(true),
+        (Pattern::Binding(variable), _) => {
+            bindings.push((variable, value.clone()));
+            Ok(true)
+        }
+        (Pattern::Literal(literal), _) => Ok(eval_expression(literal, env)? == *value),
+        (Pattern::Array(patterns, rest), Object::Array(items)) => {
+            let items = items.borrow().clone();
+            let fits = match rest {
+                Some(_) => items.len() >= patterns.len(),
+                None => items.len() == patterns.len(),
+            };
+            if !fits || !match_patterns(patterns, &items, bindings, env)? {
+                return Ok(false);
+            }
+            match rest {
+                Some(rest) => {
+                    let rest_items = Object::array(items[patterns.len()..].to_vec());
+                    match_pattern(rest, &rest_items, bindings, env)
+                }
+                None => Ok(true),
+            }
+        }
+        (Pattern::Tuple(patterns), Object::Tuple(items)) => {
+            Ok(items.len() == patterns.len() && match_patterns(patterns, items, bindings, env)?)
+        }
+        (Pattern::HashMap(entries), Object::HashMap(map)) => {
+            for (key, pattern) in entries {
+                let key = hash_key(&eval_expression(key, env)?)?;
+                let entry = map.borrow().get(&key).cloned();
+                match entry {
+                    Some(entry) if match_pattern(pattern, &entry, bindings, env)? => {}
+                    _ => return Ok(false),
+                }
+            }
+            Ok(true)
+        }
+        (Pattern::Struct(name, fields, position), _) => {
+            let def = match eval_variable(name, *position, env)? {
+                Object::StructType(def, _) => def,
+                obj => {
+                    return Err(RuntimeError::new(
+                        TYPE_ERROR,
+                        format!("`{}` is not a struct, found {}", name, obj.type_name()),
+                    )
+                    .at(*position))
+                }
+            };
+            let positions = fields
+                .iter()
+                .map(|(field, _)| {
+                    def.field_position(*field).ok_or_else(|| {
+                        RuntimeError::new(
+                            ATTRIBUTE_ERROR,
+                            format!("{} has no field `{}`", def.name, field),
+                        )
+                        .at(*position)
+                    })
+                })
+                .collect::<Result<Vec<usize>, RuntimeError>>()?;
 
-pub fn eval_arr_idx(arr: &[Object], idx: &Object) -> Object {
-    match idx {
-        Object::Integer(index) => {
-            if *index as usize > arr.len() - 1 {
-                panic!("Array index {} greater that array size {}", idx, arr.len());
+            match value {
+                Object::Struct(instance) if Rc::ptr_eq(&instance.def, &def) => {
+                    for ((_, pattern), idx) in fields.iter().zip(positions) {
+                        let field_value = instance.fields.borrow()[idx].clone();
+                        if !match_pattern(pattern, &field_value, bindings, env)? {
+                            return Ok(false);
+                        }
+                    }
+                    Ok(true)
+                }
+                _ => Ok(false),
+            }
+        }
+        (Pattern::Variant(name, variant, patterns, position), _) => {
+            let def = match eval_variable(name, *position, env)? {
+                Object::EnumType(def) => def,
+                obj => {
+                    return Err(RuntimeError::new(
+                        TYPE_ERROR,
+                        format!("`{}` is not an enum, found {}", name, obj.type_name()),
+                    )
+                    .at(*position))
+                }
+            };
+            let expected = def.variant(*variant).ok_or_else(|| {
+                RuntimeError::new(
+                    ATTRIBUTE_ERROR,
+                    format!("{} has no variant `{}`", def.name, variant),
+                )
+                .at(*position)
+            })?;
+            let fields = def.variants[expected].1.len();
+            if patterns.len() != fields {
+                return Err(RuntimeError::new(
+                    TYPE_ERROR,
+                    format!(
+                        "{}.{} holds {} but the pattern has {}",
+                        def.name,
+                        variant,
+                        counted(fields, "value"),
+                        patterns.len()
+                    ),
+                )
+                .at(*position));
             }
 
-            let obj = arr.get(*index as usize).unwrap().clone();
-            obj
+            match value {
+                Object::Variant(value_def, idx, values)
+                    if Rc::ptr_eq(value_def, &def) && *idx == expected =>
+                {
+                    match_patterns(patterns, values, bindings, env)
+                }
+                _ => Ok(false),
+            }
         }
-        _ => panic!("Invalid array index {}, expected a positive integer", idx),
+        _ => Ok(false),
     }
 }
 
-pub fn eval_dict_idx(dict: &HashMap<Object, Object>, idx: &Object) -> Object {
-    let mut ret_val = Object::Null;
-    if dict.contains_key(idx) {
-        ret_val = dict.get(idx).unwrap().clone();
+///
+///  The values `pattern` binds its variables to, for the destructuring
+///  `let` statements and parameters whose pattern has to match
+///
+fn destructure<'a>(
+    pattern: &'a Pattern,
+    value: &Object,
+    env: &mut Rc<RefCell<EnviromentVariables>>,
+) -> Result<Vec<(&'a Variable, Object)>, RuntimeError> {
+    let mut bindings = vec![];
+    if !match_pattern(pattern, value, &mut bindings, env)? {
+        return Err(RuntimeError::new(
+            MATCH_ERROR,
+            format!("{} does not match the pattern {}", value, pattern),
+        ));
     }
-    ret_val
+    Ok(bindings)
 }
 
-pub fn eval_function_parameters(
-    params: &[Expression],
+fn match_patterns<'a>(
+    patterns: &'a [Pattern],
+    items: &[Object],
+    bindings: &mut Vec<(&'a Variable, Object)>,
     env: &mut Rc<RefCell<EnviromentVariables>>,
-) -> Vec<Object> {
-    let mut param_objs = vec![];
-    for param in params.iter() {
-        let param_obj = eval_expression(param, env);
-        if param_obj == Object::Null {
-            panic!("Unable to evaluate parameter {}", param);
+) -> Result<bool, RuntimeError> {
+    for (pattern, item) in patterns.iter().zip(items) {
+        if !match_pattern(pattern, item, bindings, env)? {
+            return Ok(false);
         }
-        param_objs.push(eval_expression(param, env))
     }
-    param_objs
+    Ok(true)
 }
 
-pub fn eval_index(
-    container_expr: &Expression,
-    idx_expr: &Expression,
+pub fn eval_try_expression(
+    body: &BlockStatement,
+    catch: &Option<CatchClause>,
+    finally: &Option<Box<BlockStatement>>,
     env: &mut Rc<RefCell<EnviromentVariables>>,
-) -> Object {
-    let container = eval_expression(container_expr, env);
-    let idx = eval_expression(idx_expr, env);
+) -> EvalResult {
+    let result = match (eval_scoped_block(body, env), catch) {
+        (Err(mut err), Some(catch)) => {
+            let caught = match err.value.take() {
+                Some(value) => *value,
+                None => Object::Err(err),
+            };
+            let locals = catch.locals.borrow().clone();
+            let mut catch_env = gc::allocate(EnviromentVariables::extend(env.clone(), locals));
+            catch_env.borrow_mut().define(&catch.name, caught);
+            eval_block_statement(&catch.body, &mut catch_env)
+        }
+        (result, _) => result,
+    };
 
-    match container {
-        Object::Array(arr) => eval_arr_idx(&arr, &idx),
-        Object::HashMap(dict) => eval_dict_idx(&dict, &idx),
-        _ => panic!("Expected array or dictionary got {}", container),
+    if let Some(finally_block) = finally {
+        eval_scoped_block(finally_block, env)?;
     }
+
+    result
 }
 
-pub fn eval_function_call(
-    func_expr: &Expression,
-    parameters: &[Expression],
+pub fn eval_throw_statement(
+    expr: &Expression,
+    position: Position,
     env: &mut Rc<RefCell<EnviromentVariables>>,
-) -> Object {
-    let func_obj = eval_expression(func_expr, env);
-    let param_objs = eval_function_parameters(parameters, env);
+) -> EvalResult {
+    let err = match eval_expression(expr, env)? {
+        Object::Err(err) => err,
+        value => RuntimeError::thrown(value),
+    };
 
-    match func_obj {
-        Object::FunctionLiteral(params, block, mut func_env) => {
-            if param_objs.len() != params.len() {
-                panic!("Did not find the expected number of arguments for the function");
-            }
-            eval_user_defined_function_call(&params, &param_objs, &block, &mut func_env)
-        }
-        Object::BuiltInFunction(_) => eval_builtin(&func_obj, &param_objs),
-        _ => panic!("Invalid object type {}, expected function object", func_obj),
-    }
+    Err(err.or_at(position))
 }
 
-pub fn eval_expression(
-    expr: &Expression,
-    env: &mut Rc<RefCell<EnviromentVariables>>
-) -> Object {
-    match expr {
-        Expression::IntegerLiteral(i) => Object::Integer(*i),
-        Expression::Identifier(_s) => eval_identifier(expr, env),
-        Expression::String(s) => Object::String(s.to_string()),
-        Expression::Bool(b) => Object::Bool(*b),
-        Expression::Prefix(prefix, expr) => eval_prefix_expression(prefix, expr, env),
-        Expression::Infix(infix, left, right) => eval_infix_expression(infix, left, right, env),
-        Expression::If(expr, true_block, false_block) => {
-            eval_if_expression(expr, true_block, false_block, env)
+///
+///  Binds call arguments to the parameters of `function` in `env`: positional
+///  arguments first, then named ones, then defaults, which are evaluated in
+///  `env` so they can refer to earlier parameters. Surplus positional
+///  arguments are collected by a trailing variadic parameter
+///
+pub fn bind_arguments(
+    function: &str,
+    params: &[Parameter],
+    args: &[Object],
+    named: &[(Symbol, Object)],
+    env: &mut Rc<RefCell<EnviromentVariables>>,
+) -> Result<(), RuntimeError> {
+    let fixed = params.iter().filter(|p| !p.variadic).count();
+    let variadic = params.iter().any(|p| p.variadic);
+    if !variadic && args.len() > fixed {
+        return Err(RuntimeError::new(
+            ARGUMENT_ERROR,
+            format!(
+                "{} takes at most {} but {}",
+                function,
+                counted(fixed, "argument"),
+                were_given(args.len())
+            ),
+        ));
+    }
+
+    for (name, _) in named {
+        if !params.iter().any(|p| !p.variadic && p.name == *name) {
+            return Err(RuntimeError::new(
+                ARGUMENT_ERROR,
+                format!("{} got an unexpected named argument `{}`", function, name),
+            ));
         }
-        Expression::ArrayLiteral(arr) => eval_array_literal(arr, env),
-        Expression::HashMapLiteral(dict) => eval_dict_literal(dict, env),
-        Expression::Index(arr, idx) => eval_index(arr, idx, env),
-        Expression::FunctionLiteral(params, block) => {
-            Object::FunctionLiteral(params.clone(), *block.clone(), env.clone())
+    }
+
+    for (idx, param) in params.iter().enumerate() {
+        let value = if param.variadic {
+            Object::array(args.get(idx..).unwrap_or(&[]).to_vec())
+        } else {
+            let named_value = named.iter().find(|(n, _)| *n == param.name);
+            match (args.get(idx), named_value, &param.default) {
+                (Some(_), Some(_), _) => {
+                    return Err(RuntimeError::new(
+                        ARGUMENT_ERROR,
+                        format!(
+                            "{} got multiple values for argument `{}`",
+                            function, param.name
+                        ),
+                    ))
+                }
+                (Some(value), None, _) | (None, Some((_, value)), _) => value.clone(),
+                (None, None, Some(default)) => eval_expression(default, env)?,
+                (None, None, None) => {
+                    return Err(RuntimeError::new(
+                        ARGUMENT_ERROR,
+                        format!("{} missing required argument `{}`", function, param.label()),
+                    ))
+                }
+            }
+        };
+        // Parameters take the first slots of the call's scope
+        env.borrow_mut().set_local(0, idx, value.clone());
+        if let Some(pattern) = &param.pattern {
+            for (variable, value) in destructure(pattern, &value, env)? {
+                env.borrow_mut().define(variable, value);
+            }
         }
-        Expression::Call(func, params) => eval_function_call(func, params, env),
     }
+
+    Ok(())
 }
 
-pub fn eval_let_statement(
-    identifier: String,
-    expr: &Expression,
+pub fn eval_dict_literal(
+    dict_expr: &Vec<(Expression, Expression, Position)>,
     env: &mut Rc<RefCell<EnviromentVariables>>,
-) -> Object {
-    let expr_val = eval_expression(expr, env);
-    env.borrow_mut().set(identifier.as_str(), expr_val);
-    Object::Null
+) -> EvalResult {
+    let mut dict = IndexMap::new();
+    for (key_expr, val_expr, position) in dict_expr {
+        let key = hash_key(&eval_expression(key_expr, env)?).map_err(|err| err.at(*position))?;
+        let val = eval_expression(val_expr, env)?;
+        dict.insert(key, val);
+    }
+
+    Ok(Object::hashmap(dict))
 }
 
-pub fn eval_return_statement(
-    expr: &Expression,
+pub fn eval_array_literal(
+    member_expr: &[Expression],
     env: &mut Rc<RefCell<EnviromentVariables>>,
-) -> Object {
-    eval_expression(expr, env)
+) -> EvalResult {
+    let mut members = vec![];
+    for mem in member_expr.iter() {
+        members.push(eval_expression(mem, env)?);
+    }
+    Ok(Object::array(members))
 }
 
-pub fn eval_program(program: &Program, env: &mut Rc<RefCell<EnviromentVariables>>) -> Object {
-    let mut val = Object::Null;
-    for stmt in &program.stmts {
-        val = match stmt {
-            Statement::Let(id, expr) => eval_let_statement(id.to_string(), expr, env),
-            Statement::Return(Some(expr)) => {
-                return eval_return_statement(expr, env);
-            }
-            Statement::Return(None) => {
-                return Object::Null;
-            }
-            Statement::Expression(expr) => eval_expression(expr, env),
-        };
+pub fn eval_tuple_literal(
+    member_expr: &[Expression],
+    env: &mut Rc<RefCell<EnviromentVariables>>,
+) -> EvalResult {
+    let mut members = vec![];
+    for mem in member_expr.iter() {
+        members.push(eval_expression(mem, env)?);
     }
-    val
+    Ok(Object::tuple(members))
 }
 
-#[cfg(test)]
-mod tests {
-    use crate::enviroment::EnviromentVariables;
-    use crate::evaluator::*;
-    use crate::lexer::Lexer;
-    use crate::object::Object;
-    use crate::parser::Parser;
+///
+///  Turns `idx` into a position in a container of `len` items. Negative
+///  indices count from the end, so `-1` is the last item
+///
+fn index_position(container: &Object, len: usize, idx: &Object) -> Result<usize, RuntimeError> {
+    let kind = container.type_name();
+    let index = match idx {
+        Object::Integer(index) => *index,
+        _ => {
+            return Err(RuntimeError::new(
+                TYPE_ERROR,
+                format!("invalid {} index {}, expected an integer", kind, idx),
+            ))
+        }
+    };
 
-    fn test_eval_program(input: &str) -> Object {
-        let mut env = Rc::new(RefCell::new(EnviromentVariables::new()));
-        let lexer = Lexer::new(input);
-        let mut parser = Parser::new(lexer);
-        let program = parser.parse_program().unwrap();
-        eval_program(program.as_ref(), &mut env)
+    let position = match index {
+        0.. => Some(index as usize),
+        _ => len.checked_sub(index.unsigned_abs() as usize),
+    };
+    match position {
+        Some(position) if position < len => Ok(position),
+        _ => Err(RuntimeError::new(
+            INDEX_ERROR,
+            format!(
+                "{} index {} out of range for {} of length {}",
+                kind, index, kind, len
+            ),
+        )),
     }
+}
 
-    struct TestCase<'a> {
-        test_str: &'a str,
-        val: Object,
-    }
+///
+///  The positions a slice covers. Like Python, bounds may be negative and
+///  are clamped to the container instead of raising an error
+///
+fn slice_positions(len: usize, start: Option<i64>, end: Option<i64>) -> (usize, usize) {
+    let clamp = |bound: i64| match bound {
+        0.. => (bound as usize).min(len),
+        _ => len.saturating_sub(bound.unsigned_abs() as usize),
+    };
+    let start = start.map_or(0, clamp);
+    let end = end.map_or(len, clamp);
+    (start, end.max(start))
+}
+
+fn offset(start: i64, position: usize) -> i64 {
+    (start as i128 + position as i128) as i64
+}
+
+pub fn eval_container_slice(
+    container: &Object,
+    start: Option<i64>,
+    end: Option<i64>,
+) -> EvalResult {
+    match container {
+        Object::Array(items) => {
+            let items = items.borrow();
+            let (start, end) = slice_positions(items.len(), start, end);
+            Ok(Object::array(items[start..end].to_vec()))
+        }
+        Object::Tuple(items) => {
+            let (start, end) = slice_positions(items.len(), start, end);
+            Ok(Object::tuple(items[start..end].to_vec()))
+        }
+        Object::String(s) => {
+            let (start, end) = slice_positions(s.chars().count(), start, end);
+            Ok(Object::String(
+                s.chars().skip(start).take(end - start).collect(),
+            ))
+        }
+        Object::Range(first, last) => {
+            let (start, end) = slice_positions(range_len(*first, *last), start, end);
+            Ok(Object::Range(offset(*first, start), offset(*first, end)))
+        }
+        _ => Err(RuntimeError::new(
+            TYPE_ERROR,
+            format!("{} is not sliceable", container.type_name()),
+        )),
+    }
+}
+
+fn eval_slice_bound(
+    bound: &Option<Box<Expression>>,
+    env: &mut Rc<RefCell<EnviromentVariables>>,
+) -> Result<Option<i64>, RuntimeError> {
+    let bound = match bound {
+        Some(bound) => eval_expression(bound, env)?,
+        None => return Ok(None),
+    };
+    match bound {
+        Object::Integer(bound) => Ok(Some(bound)),
+        _ => Err(RuntimeError::new(
+            TYPE_ERROR,
+            format!("invalid slice bound {}, expected an integer", bound),
+        )),
+    }
+}
+
+fn hash_key(key: &Object) -> Result<HashKey, RuntimeError> {
+    key.hash_key().map_err(|unhashable| {
+        RuntimeError::new(
+            TYPE_ERROR,
+            format!("unhashable type: {}", unhashable.type_name()),
+        )
+    })
+}
+
+pub fn eval_dict_idx(dict: &IndexMap<HashKey, Object>, idx: &Object) -> EvalResult {
+    let key = hash_key(idx)?;
+    Ok(dict.get(&key).cloned().unwrap_or(Object::Null))
+}
+
+pub fn eval_err_idx(err: &RuntimeError, idx: &Object) -> EvalResult {
+    match idx {
+        Object::String(field) => err
+            .fields()
+            .shift_remove(&HashKey::String(field.clone()))
+            .ok_or_else(|| {
+                RuntimeError::new(
+                    INDEX_ERROR,
+                    format!("error object has no field \"{}\"", field),
+                )
+            }),
+        _ => Err(RuntimeError::new(
+            TYPE_ERROR,
+            format!("invalid error field {}, expected a string", idx),
+        )),
+    }
+}
+
+pub type EvaluatedArguments = (Vec<Object>, Vec<(Symbol, Object)>);
+
+pub fn eval_arguments(
+    args: &[Argument],
+    position: Position,
+    env: &mut Rc<RefCell<EnviromentVariables>>,
+) -> Result<EvaluatedArguments, RuntimeError> {
+    let mut positional = vec![];
+    let mut named = vec![];
+    for arg in args.iter() {
+        match arg {
+            Argument::Positional(expr) => positional.push(eval_expression(expr, env)?),
+            Argument::Spread(expr) => {
+                let value = eval_expression(expr, env)?;
+                let items = value.items().ok_or_else(|| {
+                    RuntimeError::new(
+                        TYPE_ERROR,
+                        format!("cannot spread {}, expected an iterable", value.type_name()),
+                    )
+                    .at(position)
+                })?;
+                positional.extend(items);
+            }
+            Argument::Named(name, expr) => named.push((*name, eval_expression(expr, env)?)),
+        }
+    }
+    Ok((positional, named))
+}
+
+pub fn eval_index(
+    container_expr: &Expression,
+    idx_expr: &Expression,
+    position: Position,
+    env: &mut Rc<RefCell<EnviromentVariables>>,
+) -> EvalResult {
+    let container = eval_expression(container_expr, env)?;
+    eval_subscript(&container, idx_expr, position, env)
+}
+
+pub fn eval_optional_index(
+    container_expr: &Expression,
+    idx_expr: &Expression,
+    position: Position,
+    env: &mut Rc<RefCell<EnviromentVariables>>,
+) -> EvalResult {
+    let container = eval_expression(container_expr, env)?;
+    if container == Object::Null {
+        return Ok(Object::Null);
+    }
+
+    eval_subscript(&container, idx_expr, position, env)
+}
+
+fn eval_subscript(
+    container: &Object,
+    idx_expr: &Expression,
+    position: Position,
+    env: &mut Rc<RefCell<EnviromentVariables>>,
+) -> EvalResult {
+    match idx_expr {
+        Expression::Slice(start, end) => {
+            let start = eval_slice_bound(start, env).map_err(|e| e.or_at(position))?;
+            let end = eval_slice_bound(end, env).map_err(|e| e.or_at(position))?;
+            eval_container_slice(container, start, end).map_err(|e| e.or_at(position))
+        }
+        _ => {
+            let idx = eval_expression(idx_expr, env)?;
+            eval_container_idx(container, &idx).map_err(|e| e.or_at(position))
+        }
+    }
+}
+
+///
+///  Stores `value` into an array slot or hashmap entry. Containers are shared,
+///  so every holder of `container` sees the change
+///
+pub fn eval_index_assign_expression(
+    container_expr: &Expression,
+    idx_expr: &Expression,
+    value_expr: &Expression,
+    position: Position,
+    env: &mut Rc<RefCell<EnviromentVariables>>,
+) -> EvalResult {
+    let container = eval_expression(container_expr, env)?;
+    let idx = eval_expression(idx_expr, env)?;
+    let value = eval_expression(value_expr, env)?;
+
+    match &container {
+        Object::Array(arr) => {
+            let mut arr = arr.borrow_mut();
+            let idx = index_position(&container, arr.len(), &idx).map_err(|e| e.at(position))?;
+            arr[idx] = value.clone();
+        }
+        Object::HashMap(dict) => {
+            let key = hash_key(&idx).map_err(|e| e.at(position))?;
+            dict.borrow_mut().insert(key, value.clone());
+        }
+        _ => {
+            return Err(RuntimeError::new(
+                TYPE_ERROR,
+                format!("{} does not support item assignment", container.type_name()),
+            )
+            .at(position))
+        }
+    }
+    Ok(value)
+}
+
+/// `count` and `noun`, made plural unless `count` is 1, like `1 value` or `2 values`
+fn counted(count: usize, noun: &str) -> String {
+    match count {
+        1 => format!("1 {}", noun),
+        _ => format!("{} {}s", count, noun),
+    }
+}
+
+fn were_given(count: usize) -> String {
+    match count {
+        1 => "1 was given".to_string(),
+        _ => format!("{} were given", count),
+    }
+}
+
+/// `member` is what was looked up on `object`, a field or a method
+fn no_member(object: &Object, member: &str, name: Symbol) -> RuntimeError {
+    let owner = match object {
+        Object::Struct(instance) => instance.def.name.to_string(),
+        Object::EnumType(def) | Object::Variant(def, ..) => def.name.to_string(),
+        _ => object.type_name().to_string(),
+    };
+    RuntimeError::new(
+        ATTRIBUTE_ERROR,
+        format!("{} has no {} `{}`", owner, member, name),
+    )
+}
+
+fn struct_field(object: &Object, field: Symbol) -> Result<(&StructRef, usize), RuntimeError> {
+    match object {
+        Object::Struct(instance) => match instance.def.field_position(field) {
+            Some(idx) => Ok((instance, idx)),
+            None => Err(no_member(object, "field", field)),
+        },
+        _ => Err(no_member(object, "field", field)),
+    }
+}
+
+pub fn eval_field(
+    object_expr: &Expression,
+    field: Symbol,
+    position: Position,
+    env: &mut Rc<RefCell<EnviromentVariables>>,
+) -> EvalResult {
+    let object = eval_expression(object_expr, env)?;
+    field_value(&object, field, position)
+}
+
+pub fn eval_optional_field(
+    object_expr: &Expression,
+    field: Symbol,
+    position: Position,
+    env: &mut Rc<RefCell<EnviromentVariables>>,
+) -> EvalResult {
+    let object = eval_expression(object_expr, env)?;
+    if object == Object::Null {
+        return Ok(Object::Null);
+    }
+
+    field_value(&object, field, position)
+}
+
+fn field_value(object: &Object, field: Symbol, position: Position) -> EvalResult {
+    if let Object::EnumType(def) = object {
+        return eval_variant(def, field, vec![]).map_err(|e| e.at(position));
+    }
+
+    let (instance, idx) = struct_field(object, field).map_err(|e| e.at(position))?;
+    let value = instance.fields.borrow()[idx].clone();
+    Ok(value)
+}
+
+///
+///  Builds the variant `name` of an enum, as `Shape.Empty` or
+///  `Shape.Circle(2)` do, which has to be given one value per field
+///
+fn eval_variant(def: &Rc<EnumDef>, name: Symbol, values: Vec<Object>) -> EvalResult {
+    let variant = def.variant(name).ok_or_else(|| {
+        RuntimeError::new(
+            ATTRIBUTE_ERROR,
+            format!("{} has no variant `{}`", def.name, name),
+        )
+    })?;
+
+    let fields = &def.variants[variant].1;
+    if values.len() != fields.len() {
+        return Err(RuntimeError::new(
+            ARGUMENT_ERROR,
+            format!(
+                "{}.{} takes {} but {}",
+                def.name,
+                name,
+                counted(fields.len(), "value"),
+                were_given(values.len())
+            ),
+        ));
+    }
+    Ok(Object::variant(def.clone(), variant, values))
+}
+
+///
+///  Stores `value` into a field of a struct instance, which every holder of
+///  the instance sees
+///
+pub fn eval_field_assign(
+    object_expr: &Expression,
+    field: Symbol,
+    value_expr: &Expression,
+    position: Position,
+    env: &mut Rc<RefCell<EnviromentVariables>>,
+) -> EvalResult {
+    let object = eval_expression(object_expr, env)?;
+    let value = eval_expression(value_expr, env)?;
+    let (instance, idx) = struct_field(&object, field).map_err(|e| e.at(position))?;
+    instance.fields.borrow_mut()[idx] = value.clone();
+    Ok(value)
+}
+
+///
+///  Builds an instance of the struct `name` refers to. Every field has to be
+///  given exactly once, in any order
+///
+pub fn eval_struct_literal(
+    name: &Variable,
+    field_exprs: &[(Symbol, Expression)],
+    position: Position,
+    env: &mut Rc<RefCell<EnviromentVariables>>,
+) -> EvalResult {
+    let (def, struct_env) = match eval_variable(name, position, env)? {
+        Object::StructType(def, struct_env) => (def, struct_env),
+        obj => {
+            return Err(RuntimeError::new(
+                TYPE_ERROR,
+                format!("`{}` is not a struct, found {}", name, obj.type_name()),
+            )
+            .at(position))
+        }
+    };
+
+    let mut fields = vec![None; def.fields.len()];
+    for (field, expr) in field_exprs {
+        let value = eval_expression(expr, env)?;
+        match def.field_position(*field) {
+            Some(idx) => fields[idx] = Some(value),
+            None => {
+                return Err(RuntimeError::new(
+                    ATTRIBUTE_ERROR,
+                    format!("{} has no field `{}`", def.name, field),
+                )
+                .at(position))
+            }
+        }
+    }
+
+    let fields = fields
+        .into_iter()
+        .zip(&def.fields)
+        .map(|(value, field)| {
+            value.ok_or_else(|| {
+                RuntimeError::new(
+                    TYPE_ERROR,
+                    format!("{} is missing field `{}`", def.name, field),
+                )
+                .at(position)
+            })
+        })
+        .collect::<Result<Vec<Object>, RuntimeError>>()?;
+    Ok(Object::instance(def, struct_env, fields))
+}
+
+pub fn eval_container_idx(container: &Object, idx: &Object) -> EvalResult {
+    match container {
+        Object::Array(arr) => {
+            let arr = arr.borrow();
+            Ok(arr[index_position(container, arr.len(), idx)?].clone())
+        }
+        Object::Tuple(items) => Ok(items[index_position(container, items.len(), idx)?].clone()),
+        Object::String(s) => {
+            let position = index_position(container, s.chars().count(), idx)?;
+            Ok(Object::String(s.chars().nth(position).unwrap().to_string()))
+        }
+        Object::Range(start, end) => {
+            let position = index_position(container, range_len(*start, *end), idx)?;
+            Ok(Object::Integer(offset(*start, position)))
+        }
+        Object::HashMap(dict) => eval_dict_idx(&dict.borrow(), idx),
+        Object::Err(err) => eval_err_idx(err, idx),
+        _ => Err(RuntimeError::new(
+            TYPE_ERROR,
+            format!("{} is not indexable", container.type_name()),
+        )),
+    }
+}
+
+///
+///  Calls `receiver.method(args)`. A struct's own methods and the methods of
+///  builtin types get the receiver as their first argument, while a struct
+///  field holding a function is called with just `args`
+///
+pub fn eval_method_call(
+    receiver_expr: &Expression,
+    method: Symbol,
+    args: &[Argument],
+    position: Position,
+    env: &mut Rc<RefCell<EnviromentVariables>>,
+) -> EvalResult {
+    let receiver = eval_expression(receiver_expr, env)?;
+    let (mut positional, named) = eval_arguments(args, position, env)?;
+
+    if let Object::EnumType(def) = &receiver {
+        if !named.is_empty() {
+            return Err(RuntimeError::new(
+                ARGUMENT_ERROR,
+                format!("{}.{} does not accept named arguments", def.name, method),
+            )
+            .at(position));
+        }
+        return eval_variant(def, method, positional).map_err(|e| e.at(position));
+    }
+
+    let (func_obj, function, pass_receiver) = match &receiver {
+        Object::Struct(instance) => match instance.def.method(method) {
+            Some(function) => {
+                let name = format!("{}.{}", instance.def.name, method);
+                let func = Object::FunctionLiteral(
+                    Some(name.clone()),
+                    function.clone(),
+                    instance.env.clone(),
+                );
+                (func, name, true)
+            }
+            None => match instance.def.field_position(method) {
+                Some(idx) => {
+                    let field = instance.fields.borrow()[idx].clone();
+                    (field, method.to_string(), false)
+                }
+                None => return Err(no_member(&receiver, "method", method).at(position)),
+            },
+        },
+        _ => match get_method(&receiver, &method.name()) {
+            Some(builtin) => (builtin, method.to_string(), true),
+            None => return Err(no_member(&receiver, "method", method).at(position)),
+        },
+    };
+
+    if pass_receiver {
+        positional.insert(0, receiver);
+    }
+    apply_function(func_obj, &positional, &named, Frame { function, position })
+}
+
+fn call_frame(func_obj: &Object, func_expr: &Expression, position: Position) -> Frame {
+    let function = match (func_obj, func_expr) {
+        (Object::FunctionLiteral(Some(name), ..), _) => name.clone(),
+        (_, Expression::Identifier(variable, _)) => variable.to_string(),
+        _ => String::from("<anonymous>"),
+    };
+
+    Frame { function, position }
+}
+
+pub fn eval_function_call(
+    func_expr: &Expression,
+    args: &[Argument],
+    position: Position,
+    env: &mut Rc<RefCell<EnviromentVariables>>,
+) -> EvalResult {
+    let func_obj = eval_expression(func_expr, env)?;
+    let (positional, named) = eval_arguments(args, position, env)?;
+    let frame = call_frame(&func_obj, func_expr, position);
+    apply_function(func_obj, &positional, &named, frame)
+}
+
+pub fn eval_optional_call(
+    func_expr: &Expression,
+    args: &[Argument],
+    position: Position,
+    env: &mut Rc<RefCell<EnviromentVariables>>,
+) -> EvalResult {
+    let func_obj = eval_expression(func_expr, env)?;
+    if func_obj == Object::Null {
+        return Ok(Object::Null);
+    }
+
+    let (positional, named) = eval_arguments(args, position, env)?;
+    let frame = call_frame(&func_obj, func_expr, position);
+    apply_function(func_obj, &positional, &named, frame)
+}
+
+///
+///  Calls a function a builtin was given, like the one `map` applies to every
+///  item, with `position` being where the builtin was called
+///
+pub fn apply_callback(func_obj: Object, args: &[Object], position: Position) -> EvalResult {
+    let function = function_name(&func_obj).unwrap_or_else(|| String::from("<anonymous>"));
+    apply_function(func_obj, args, &[], Frame { function, position })
+}
+
+fn function_name(func_obj: &Object) -> Option<String> {
+    match func_obj {
+        Object::FunctionLiteral(Some(name), ..) | Object::BuiltInFunction(name) => {
+            Some(name.clone())
+        }
+        _ => None,
+    }
+}
+
+pub fn apply_function(
+    func_obj: Object,
+    param_objs: &[Object],
+    named: &[(Symbol, Object)],
+    frame: Frame,
+) -> EvalResult {
+    let position = frame.position;
+    match func_obj {
+        Object::FunctionLiteral(_, function, func_env) => {
+            let locals = function.locals.borrow().clone();
+            let mut func_new_env = gc::allocate(EnviromentVariables::extend(func_env, locals));
+
+            if CALL_STACK.with(|stack| stack.borrow().len()) >= MAX_CALL_DEPTH {
+                return Err(RuntimeError::new(
+                    RECURSION_ERROR,
+                    format!("maximum call depth of {} exceeded", MAX_CALL_DEPTH),
+                )
+                .at(position));
+            }
+
+            // Defaults run inside the call, so they count towards its depth
+            // and show in its traces
+            let function_name = frame.function.clone();
+            CALL_STACK.with(|stack| stack.borrow_mut().push(frame));
+            let result = bind_arguments(
+                &function_name,
+                &function.params,
+                param_objs,
+                named,
+                &mut func_new_env,
+            )
+            .map_err(|e| e.or_at(position))
+            .and_then(|_| eval_block_statement(&function.body, &mut func_new_env));
+            CALL_STACK.with(|stack| stack.borrow_mut().pop());
+            result
+        }
+        Object::BuiltInFunction(ref name) if !named.is_empty() => Err(RuntimeError::new(
+            ARGUMENT_ERROR,
+            format!("builtin `{}` does not accept named arguments", name),
+        )
+        .at(position)),
+        Object::BuiltInFunction(_) => {
+            eval_builtin(&func_obj, param_objs, position).map_err(|e| e.or_at(position))
+        }
+        Object::Composition(first, second) => {
+            // Each part shows in traces under its own name when it has one
+            let part_frame = |part: &Object| Frame {
+                function: function_name(part).unwrap_or_else(|| frame.function.clone()),
+                position,
+            };
+            let first_frame = part_frame(&first);
+            let second_frame = part_frame(&second);
+            let value = apply_function(*first, param_objs, named, first_frame)?;
+            apply_function(*second, &[value], &[], second_frame)
+        }
+        _ => Err(RuntimeError::new(
+            TYPE_ERROR,
+            format!("{} is not a function", func_obj.type_name()),
+        )
+        .at(position)),
+    }
+}
+
+pub fn eval_expression(
+    expr: &Expression,
+    env: &mut Rc<RefCell<EnviromentVariables>>,
+) -> EvalResult {
+    match expr {
+        Expression::IntegerLiteral(i) => Ok(Object::Integer(*i)),
+        Expression::Identifier(..) => eval_identifier(expr, env),
+        Expression::String(s) => Ok(Object::String(s.to_string())),
+        Expression::Bool(b) => Ok(Object::Bool(*b)),
+        Expression::Null => Ok(Object::Null),
+        Expression::Prefix(prefix, expr, position) => {
+            eval_prefix_expression(prefix, expr, *position, env)
+        }
+        Expression::Infix(infix, left, right, position) => {
+            eval_infix_expression(infix, left, right, *position, env)
+        }
+        Expression::Assign(name, expr, position) => {
+            eval_assign_expression(name, expr, *position, env)
+        }
+        Expression::IndexAssign(container, idx, expr, position) => {
+            eval_index_assign_expression(container, idx, expr, *position, env)
+        }
+        Expression::If(branches, false_block) => eval_if_expression(branches, false_block, env),
+        Expression::ArrayLiteral(arr) => eval_array_literal(arr, env),
+        Expression::TupleLiteral(members) => eval_tuple_literal(members, env),
+        Expression::HashMapLiteral(dict) => eval_dict_literal(dict, env),
+        Expression::Index(arr, idx, position) => eval_index(arr, idx, *position, env),
+        Expression::OptionalIndex(arr, idx, position) => {
+            eval_optional_index(arr, idx, *position, env)
+        }
+        Expression::Slice(..) => unreachable!("Slices are only parsed as an index"),
+        Expression::Field(object, field, position) => eval_field(object, *field, *position, env),
+        Expression::OptionalField(object, field, position) => {
+            eval_optional_field(object, *field, *position, env)
+        }
+        Expression::MethodCall(receiver, method, args, position) => {
+            eval_method_call(receiver, *method, args, *position, env)
+        }
+        Expression::FieldAssign(object, field, value, position) => {
+            eval_field_assign(object, *field, value, *position, env)
+        }
+        Expression::StructLiteral(name, fields, position) => {
+            eval_struct_literal(name, fields, *position, env)
+        }
+        Expression::FunctionLiteral(function) => {
+            Ok(Object::FunctionLiteral(None, function.clone(), env.clone()))
+        }
+        Expression::Call(func, params, position) => {
+            eval_function_call(func, params, *position, env)
+        }
+        Expression::OptionalCall(func, params, position) => {
+            eval_optional_call(func, params, *position, env)
+        }
+        Expression::Try(body, catch, finally) => eval_try_expression(body, catch, finally, env),
+        Expression::Block(block) => eval_scoped_block(block, env),
+        Expression::Ternary(cond, true_expr, false_expr, position) => {
+            eval_ternary_expression(cond, true_expr, false_expr, *position, env)
+        }
+        Expression::Match(subject, arms, position) => {
+            eval_match_expression(subject, arms, *position, env)
+        }
+    }
+}
+
+///
+///  Runs a `let`, `let mut` or `const`, binding its variables with `define`.
+///  None of them can declare a global constant again
+///
+pub fn eval_let_statement(
+    pattern: &Pattern,
+    expr: &Expression,
+    define: fn(&mut EnviromentVariables, &Variable, Object),
+    position: Position,
+    env: &mut Rc<RefCell<EnviromentVariables>>,
+) -> EvalResult {
+    let bindings = match (pattern, eval_expression(expr, env)?) {
+        (Pattern::Binding(variable), Object::FunctionLiteral(None, function, func_env))
+            if matches!(expr, Expression::FunctionLiteral(..)) =>
+        {
+            let name = Some(variable.to_string());
+            vec![(variable, Object::FunctionLiteral(name, function, func_env))]
+        }
+        (Pattern::Binding(variable), val) => vec![(variable, val)],
+        (_, val) => destructure(pattern, &val, env).map_err(|e| e.at(position))?,
+    };
+
+    if let Some((variable, _)) = bindings.iter().find(|(v, _)| env.borrow().is_constant(v)) {
+        return Err(RuntimeError::new(
+            TYPE_ERROR,
+            format!("cannot redeclare constant `{}`", variable),
+        )
+        .at(position));
+    }
+    for (variable, value) in bindings {
+        define(&mut env.borrow_mut(), variable, value);
+    }
+    Ok(Object::Null)
+}
+
+pub fn eval_return_statement(
+    expr: &Expression,
+    env: &mut Rc<RefCell<EnviromentVariables>>,
+) -> EvalResult {
+    eval_expression(expr, env)
+}
+
+pub fn eval_program(program: &Program, env: &mut Rc<RefCell<EnviromentVariables>>) -> EvalResult {
+    hoist_declarations(&program.stmts, env);
+
+    let mut val = Object::Null;
+    for stmt in &program.stmts {
+        val = match stmt {
+            Statement::Let(pattern, expr, position) => {
+                let define = EnviromentVariables::define_immutable;
+                eval_let_statement(pattern, expr, define, *position, env)?
+            }
+            Statement::LetMut(pattern, expr, position) => {
+                let define = EnviromentVariables::define;
+                eval_let_statement(pattern, expr, define, *position, env)?
+            }
+            Statement::Const(pattern, expr, position) => {
+                let define = EnviromentVariables::define_constant;
+                eval_let_statement(pattern, expr, define, *position, env)?
+            }
+            Statement::Return(Some(expr)) => {
+                return eval_return_statement(expr, env);
+            }
+            Statement::Return(None) => {
+                return Ok(Object::Null);
+            }
+            Statement::Throw(expr, position) => return eval_throw_statement(expr, *position, env),
+            Statement::Function(..) | Statement::Struct(..) | Statement::Enum(..) => Object::Null,
+            Statement::Expression(expr) => eval_expression(expr, env)?,
+        };
+    }
+    Ok(val)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::enviroment::EnviromentVariables;
+    use crate::evaluator::*;
+    use crate::gc;
+    use crate::lexer::Lexer;
+    use crate::object::Object;
+    use crate::parser::Parser;
+    use crate::resolver::resolve_program;
+    use std::time::Instant;
+
+    fn test_eval_program(input: &str) -> Object {
+        test_eval_result(input).unwrap()
+    }
+
+    fn test_eval_error(input: &str) -> RuntimeError {
+        test_eval_result(input).unwrap_err()
+    }
+
+    fn test_eval_result(input: &str) -> EvalResult {
+        let mut env = gc::allocate(EnviromentVariables::new());
+        let lexer = Lexer::new(input).unwrap();
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program().unwrap();
+        resolve_program(&program)?;
+        eval_program(program.as_ref(), &mut env)
+    }
+
+    struct TestCase<'a> {
+        test_str: &'a str,
+        val: Object,
+    }
 
     fn check_test_cases(test_cases: Vec<TestCase>) {
         for test_case in test_cases {
@@ -391,211 +1763,1876 @@ mod tests {
     fn test_eval_prefix() {
         let test_cases = vec![
             TestCase {
-                test_str: "!true",
-                val: Object::Bool(false),
+                test_str: "!true",
+                val: Object::Bool(false),
+            },
+            TestCase {
+                test_str: "!false",
+                val: Object::Bool(true),
+            },
+            TestCase {
+                test_str: "-1",
+                val: Object::Integer(-1),
+            },
+        ];
+
+        check_test_cases(test_cases);
+    }
+
+    #[test]
+    fn test_eval_infix() {
+        let test_cases = vec![
+            TestCase {
+                test_str: "10 > 20",
+                val: Object::Bool(false),
+            },
+            TestCase {
+                test_str: "-1 + 0",
+                val: Object::Integer(-1),
+            },
+            TestCase {
+                test_str: "5 > 3",
+                val: Object::Bool(true),
+            },
+            TestCase {
+                test_str: "4 < 2",
+                val: Object::Bool(false),
+            },
+            TestCase {
+                test_str: "5 == 3",
+                val: Object::Bool(false),
+            },
+            TestCase {
+                test_str: "4 != 2",
+                val: Object::Bool(true),
+            },
+            TestCase {
+                test_str: "(2*2 + 1) == 5",
+                val: Object::Bool(true),
+            },
+            TestCase {
+                test_str: "(2 + 3)*2 == 10",
+                val: Object::Bool(true),
+            },
+            TestCase {
+                test_str: "\"ab\" + \"cd\"",
+                val: Object::String(String::from("abcd")),
+            },
+            TestCase {
+                test_str: "\"ab\" + \"cd\" + \"ef\"",
+                val: Object::String(String::from("abcdef")),
+            },
+        ];
+
+        check_test_cases(test_cases);
+    }
+
+    #[test]
+    fn test_eval_bitwise() {
+        let test_cases = vec![
+            TestCase {
+                test_str: "0b1100 & 0b1010",
+                val: Object::Integer(0b1000),
+            },
+            TestCase {
+                test_str: "0b1100 | 0b1010",
+                val: Object::Integer(0b1110),
+            },
+            TestCase {
+                test_str: "0b1100 ^ 0b1010",
+                val: Object::Integer(0b0110),
+            },
+            TestCase {
+                test_str: "~0",
+                val: Object::Integer(-1),
+            },
+            TestCase {
+                test_str: "1 << 10",
+                val: Object::Integer(1024),
+            },
+            TestCase {
+                test_str: "0xFF >> 4",
+                val: Object::Integer(0xF),
+            },
+            TestCase {
+                test_str: "1_000 + 0o10 * 2",
+                val: Object::Integer(1016),
+            },
+            TestCase {
+                test_str: "6 & 3 == 2",
+                val: Object::Bool(true),
+            },
+        ];
+
+        check_test_cases(test_cases);
+    }
+
+    #[test]
+    fn test_eval_if_expr() {
+        let test_cases = vec![
+            TestCase {
+                test_str: "if (10 > 20) {20} else {10}",
+                val: Object::Integer(10),
+            },
+            TestCase {
+                test_str: "if (21 > 20) {20} else {10}",
+                val: Object::Integer(20),
+            },
+            TestCase {
+                test_str: "if (21 > 20) {20} ",
+                val: Object::Integer(20),
+            },
+            TestCase {
+                test_str: "if (21 < 20) {20} ",
+                val: Object::Null,
+            },
+            TestCase {
+                test_str: "if (21 > 20) {let x = 30; 20} ",
+                val: Object::Integer(20),
+            },
+        ];
+
+        check_test_cases(test_cases);
+    }
+
+    #[test]
+    fn test_eval_else_if_and_ternary() {
+        let test_cases = vec![
+            TestCase {
+                test_str: "fn sign(n) { if (n > 0) { 1 } else if (n < 0) { -1 } else { 0 } }\
+                           [sign(5), sign(-5), sign(0)]",
+                val: Object::array(vec![
+                    Object::Integer(1),
+                    Object::Integer(-1),
+                    Object::Integer(0),
+                ]),
+            },
+            TestCase {
+                test_str: "if (1 > 2) { 1 } else if (1 > 3) { 2 }",
+                val: Object::Null,
+            },
+            TestCase {
+                test_str:
+                    "let x = 3; if (x < 2) { let y = 1; y } else if (x < 4) { let y = 2; y * x }",
+                val: Object::Integer(6),
+            },
+            TestCase {
+                test_str: "let n = 5; n > 3 ? n * 2 : n",
+                val: Object::Integer(10),
+            },
+            TestCase {
+                test_str:
+                    "fn size(n) { n > 100 ? 3 : n > 10 ? 2 : 1 } [size(500), size(50), size(5)]",
+                val: Object::array(vec![
+                    Object::Integer(3),
+                    Object::Integer(2),
+                    Object::Integer(1),
+                ]),
+            },
+            TestCase {
+                test_str: "let f = |x| x > 0 ? x : -x; f(-4) + f(3)",
+                val: Object::Integer(7),
+            },
+            TestCase {
+                test_str: "let picked = 1 > 2 ? fn() { 1 } : fn() { 2 }; picked()",
+                val: Object::Integer(2),
+            },
+            TestCase {
+                test_str: "let c = true; c ?[1] : [2];",
+                val: Object::array(vec![Object::Integer(1)]),
+            },
+            TestCase {
+                test_str: "let a = [false, true]; a?[1] ?[3][0] : [4][0]",
+                val: Object::Integer(3),
+            },
+        ];
+        check_test_cases(test_cases);
+
+        let test_cases = [
+            ("if (false) { 1 } else if (1) { 2 }", 27),
+            ("if (1) { 2 }", 5),
+        ];
+        for (input, column) in test_cases {
+            let err = test_eval_error(input);
+            assert_eq!(err.kind, TYPE_ERROR, "{}", input);
+            assert_eq!(
+                err.message,
+                "expected boolean condition in if expression, found integer"
+            );
+            assert_eq!(
+                err.position,
+                Some(Position { line: 1, column }),
+                "{}",
+                input
+            );
+        }
+
+        let test_cases = [
+            ("1 ? 2 : 3", 3, "integer"),
+            ("false ? 1 : \"a\" ? 2 : 3", 17, "string"),
+        ];
+        for (input, column, type_name) in test_cases {
+            let err = test_eval_error(input);
+            assert_eq!(err.kind, TYPE_ERROR, "{}", input);
+            assert_eq!(
+                err.message,
+                format!("ternary condition must be a boolean, found {}", type_name)
+            );
+            assert_eq!(
+                err.position,
+                Some(Position { line: 1, column }),
+                "{}",
+                input
+            );
+        }
+        assert_eq!(
+            test_eval_error("let x = null;\nx ? 1 : 2").traceback(),
+            "TypeError at line 2, column 3: ternary condition must be a boolean, found null"
+        );
+    }
+
+    #[test]
+    fn test_eval_let_statements() {
+        let test_cases = vec![
+            TestCase {
+                test_str: "let x = 10; if (x > 20) {20} else {10}",
+                val: Object::Integer(10),
+            },
+            TestCase {
+                test_str: "let y = 20; if (21 > y) {20} else {10}",
+                val: Object::Integer(20),
+            },
+            TestCase {
+                test_str: "let z = 30; z*z; ",
+                val: Object::Integer(900),
+            },
+            TestCase {
+                test_str: "let a = 30; let b = 40; a + b",
+                val: Object::Integer(70),
+            },
+        ];
+
+        check_test_cases(test_cases);
+    }
+
+    #[test]
+    fn test_eval_functions() {
+        let test_cases = vec![
+            TestCase {
+                test_str: "let sum = fn(x, y){ x + y;}; \
+                                  sum(10, 20);",
+                val: Object::Integer(30),
+            },
+            TestCase {
+                test_str: "let square = fn(x){x*x}; \
+                                  square(10)",
+                val: Object::Integer(100),
+            },
+            TestCase {
+                test_str: "let gt = fn(x, y){ \
+                                                if (!(x > y)) {\
+                                                        x*x\
+                                                } else {\
+                                                        y\
+                                                };\
+                                           }; \
+                                  gt(3, 2)",
+                val: Object::Integer(2),
+            },
+            TestCase {
+                test_str: "let gt = fn(x, y){ \
+                                                if (x > y) \
+                                                    {x*x} \
+                                                else {\
+                                                     y\
+                                                };\
+                                            }; \
+                                  gt(3, 2)",
+                val: Object::Integer(9),
+            },
+            TestCase {
+                test_str: "let fact = fn(x) {\
+                                                if (x > 1) {\
+                                                    x*fact(x - 1);\
+                                                } else {\
+                                                    x\
+                                                };\
+                                             }; \
+                                  fact(8);",
+                val: Object::Integer(40320),
+            },
+            TestCase {
+                test_str: "let sum = fn(x,y){x + y;};\
+                                  let sqr = fn(x){let z = sum(x, x); z*z;};\
+                                  let z = sum(2, 3) + sqr(2);\
+                                  z;",
+                val: Object::Integer(21),
+            },
+            TestCase {
+                test_str: "fn(x, y, z){x + y + z}(1, 2, 3);",
+                val: Object::Integer(6),
+            },
+            TestCase {
+                test_str: "let a = \"wx\";\
+                                let b = \"yz\";\
+                                let z = a + b;\
+                                z;",
+                val: Object::String(String::from("wxyz")),
+            },
+        ];
+
+        check_test_cases(test_cases);
+    }
+
+    #[test]
+    fn test_eval_function_declarations() {
+        let test_cases = vec![
+            TestCase {
+                test_str: "fn add(a, b) { a + b } add(2, 3)",
+                val: Object::Integer(5),
+            },
+            TestCase {
+                test_str: "let x = square(4); fn square(n) { n * n }; x",
+                val: Object::Integer(16),
+            },
+            TestCase {
+                test_str: "fn is_even(n) { if (n == 0) { true } else { is_odd(n - 1) }; }\
+                           fn is_odd(n) { if (n == 0) { false } else { is_even(n - 1) }; }\
+                           is_even(10)",
+                val: Object::Bool(true),
+            },
+            TestCase {
+                test_str:
+                    "fn outer(n) { let r = helper(n); fn helper(m) { m * 10 }; r + 1 } outer(4)",
+                val: Object::Integer(41),
+            },
+            TestCase {
+                test_str: "fn fact(n) { if (n > 1) { n * fact(n - 1) } else { 1 }; } fact(10)",
+                val: Object::Integer(3628800),
+            },
+        ];
+
+        check_test_cases(test_cases);
+
+        let func = test_eval_program("fn add(a, b) { a + b } add");
+        assert_eq!(func.to_string(), "fn add(a,b){ {(+ a b);} }");
+
+        let func = test_eval_program("let double = fn(x) { x * 2 }; double");
+        assert_eq!(func.to_string(), "fn double(x){ {(* x 2);} }");
+
+        let func = test_eval_program("let make = fn() { fn(x) { x } }; make()");
+        assert_eq!(func.to_string(), "fn(x){ {x;} }");
+
+        let err = test_eval_error("fn fail() { 1 / 0 } let alias = fail; alias()");
+        assert_eq!(err.trace[0].function, "fail");
+
+        let err = test_eval_error("helper(1); fn outer() { fn helper(x) { x } }");
+        assert_eq!(err.kind, NAME_ERROR);
+    }
+
+    #[test]
+    fn test_eval_function_arguments() {
+        let test_cases = vec![
+            TestCase {
+                test_str: "fn add(a, b = 10) { a + b } add(1)",
+                val: Object::Integer(11),
+            },
+            TestCase {
+                test_str: "fn add(a, b = 10) { a + b } add(1, 2)",
+                val: Object::Integer(3),
+            },
+            TestCase {
+                test_str: "fn scale(x, factor = x * 2) { factor } scale(4)",
+                val: Object::Integer(8),
+            },
+            TestCase {
+                test_str: "fn sub(a, b) { a - b } sub(b: 2, a: 10)",
+                val: Object::Integer(8),
+            },
+            TestCase {
+                test_str: "fn f(a, b = 2, c = 3) { a * 100 + b * 10 + c } f(1, c: 9)",
+                val: Object::Integer(129),
+            },
+            TestCase {
+                test_str: "fn rest(first, ...others) { others } rest(1, 2, 3)",
+                val: Object::array(vec![Object::Integer(2), Object::Integer(3)]),
+            },
+            TestCase {
+                test_str: "fn rest(first, ...others) { len(others) } rest(1)",
+                val: Object::Integer(0),
+            },
+            TestCase {
+                test_str: "fn add3(a, b, c) { a + b + c } let xs = [2, 3]; add3(1, ...xs)",
+                val: Object::Integer(6),
+            },
+            TestCase {
+                test_str: "fn all(...xs) { xs } all(...[1], 2, ...[])",
+                val: Object::array(vec![Object::Integer(1), Object::Integer(2)]),
+            },
+            TestCase {
+                test_str: "let f = null; f?.(...[1], a: 2)",
+                val: Object::Null,
+            },
+        ];
+
+        check_test_cases(test_cases);
+
+        let test_cases = [
+            ("fn f(a, b) { a } f(1)", "f missing required argument `b`"),
+            (
+                "fn f(a) { a } f(1, 2)",
+                "f takes at most 1 argument but 2 were given",
+            ),
+            (
+                "fn f(a, b) { a } f(1, 2, 3)",
+                "f takes at most 2 arguments but 3 were given",
+            ),
+            (
+                "fn f(a) { a } f(b: 1)",
+                "f got an unexpected named argument `b`",
+            ),
+            (
+                "fn f(a) { a } f(1, a: 2)",
+                "f got multiple values for argument `a`",
+            ),
+            (
+                "fn f(...xs) { xs } f(xs: [])",
+                "f got an unexpected named argument `xs`",
+            ),
+            (
+                "len(x: \"a\")",
+                "builtin `len` does not accept named arguments",
+            ),
+        ];
+
+        for (input, message) in test_cases {
+            let err = test_eval_error(input);
+            assert_eq!(err.kind, ARGUMENT_ERROR, "{}", input);
+            assert_eq!(err.message, message);
+        }
+
+        let err = test_eval_error("fn f(a) { a } f(...5)");
+        assert_eq!(err.kind, TYPE_ERROR);
+        assert_eq!(err.message, "cannot spread integer, expected an iterable");
+    }
+
+    #[test]
+    fn test_eval_shared_containers() {
+        let test_cases = vec![
+            TestCase {
+                test_str: "let a = [1, 2]; let b = a; b[0] = 10; a",
+                val: Object::array(vec![Object::Integer(10), Object::Integer(2)]),
+            },
+            TestCase {
+                test_str: "fn add(xs, x) { push(xs, x) } let a = []; add(a, 1); add(a, 2); len(a)",
+                val: Object::Integer(2),
+            },
+            TestCase {
+                test_str: "let m = {}; let f = fn(k) { m[k] = len(k) }; f(\"ab\"); f(\"c\"); m[\"ab\"] + m[\"c\"]",
+                val: Object::Integer(3),
+            },
+            TestCase {
+                test_str: "let grid = [[0, 0], [0, 0]]; grid[1][0] = 5; grid[1]",
+                val: Object::array(vec![Object::Integer(5), Object::Integer(0)]),
+            },
+            TestCase {
+                test_str: "let a = [1]; let b = copy(a); b[0] = 2; a[0] * 10 + b[0]",
+                val: Object::Integer(12),
+            },
+            TestCase {
+                test_str: "let a = [[1]]; let b = copy(a); b[0][0] = 2; a[0][0]",
+                val: Object::Integer(2),
+            },
+            TestCase {
+                test_str: "let a = [[1]]; let b = deep_copy(a); b[0][0] = 2; a[0][0]",
+                val: Object::Integer(1),
+            },
+            TestCase {
+                test_str: "let m = {\"k\": [1]}; let c = deep_copy(m); push(c[\"k\"], 2); len(m[\"k\"])",
+                val: Object::Integer(1),
+            },
+            TestCase {
+                test_str: "let inner = [1]; let c = deep_copy([inner, inner]); c[0][0] = 7; c[1][0]",
+                val: Object::Integer(7),
+            },
+            TestCase {
+                test_str: "let a = [1]; a[0] = 3",
+                val: Object::Integer(3),
+            },
+        ];
+
+        check_test_cases(test_cases);
+
+        let cyclic =
+            test_eval_program("let a = [1]; push(a, a); let m = {\"m\": {}}; m[\"m\"] = m; [a, m]");
+        assert_eq!(cyclic.to_string(), "[[1,[...]],{\"m\":{...}}]");
+
+        let copy =
+            test_eval_program("let a = [1]; push(a, a); let c = deep_copy(a); c[0] = 2; c[1]");
+        assert_eq!(copy.to_string(), "[2,[...]]");
+
+        let test_cases = [
+            ("let a = [1]; a[1] = 2", INDEX_ERROR),
+            ("let a = [1]; a[\"x\"] = 2", TYPE_ERROR),
+            ("let m = {}; m[[]] = 1", TYPE_ERROR),
+            ("let s = \"ab\"; s[0] = 1", TYPE_ERROR),
+            ("push(1, 2)", TYPE_ERROR),
+        ];
+        for (input, kind) in test_cases {
+            assert_eq!(test_eval_error(input).kind, kind, "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_eval_hash_keys() {
+        let test_cases = vec![
+            TestCase {
+                test_str: "let m = {true: 1, false: 2}; m[true] * 10 + m[false]",
+                val: Object::Integer(12),
+            },
+            TestCase {
+                test_str: "let m = {null: \"none\"}; m[null]",
+                val: Object::String("none".to_string()),
+            },
+            TestCase {
+                test_str: "let grid = {}; grid[(1, 2)] = \"x\"; grid[(1, 2)]",
+                val: Object::String("x".to_string()),
+            },
+            TestCase {
+                test_str: "let m = {((1, \"a\"), true): 5}; m[((1, \"a\"), true)]",
+                val: Object::Integer(5),
+            },
+            TestCase {
+                test_str: "let m = {(1, 2): 1}; m[(2, 1)]",
+                val: Object::Null,
+            },
+            TestCase {
+                test_str: "let t = (1, \"b\", [3]); push(t[2], 4); len(t) * 10 + len(t[2])",
+                val: Object::Integer(32),
+            },
+            TestCase {
+                test_str: "try { {(1, [2]): 3} } catch (e) { e[\"message\"] }",
+                val: Object::String("unhashable type: array".to_string()),
+            },
+        ];
+        check_test_cases(test_cases);
+
+        assert_eq!(
+            test_eval_program("((1, 2), (3,), ())").to_string(),
+            "((1,2),(3,),())"
+        );
+        assert_eq!(
+            test_eval_program("{(true, null): 1}").to_string(),
+            "{(true,NULL):1}"
+        );
+
+        let test_cases = [
+            ("let m = {}; m[{}] = 1", TYPE_ERROR),
+            ("let m = {}; m[fn() { 1 }]", TYPE_ERROR),
+            ("let t = (1, 2); t[0] = 3", TYPE_ERROR),
+            ("(1, 2)[2]", INDEX_ERROR),
+        ];
+        for (input, kind) in test_cases {
+            assert_eq!(test_eval_error(input).kind, kind, "{}", input);
+        }
+
+        assert_eq!(
+            test_eval_error("{[1]: 2}").traceback(),
+            "TypeError at line 1, column 2: unhashable type: array"
+        );
+        assert_eq!(
+            test_eval_error("let m = {\n  \"a\": 1,\n  (1, {}): 2\n}").traceback(),
+            "TypeError at line 3, column 3: unhashable type: hashmap"
+        );
+    }
+
+    #[test]
+    fn test_eval_ordered_hashmaps() {
+        let map = test_eval_program(
+            "let m = {\"zeta\": 1, \"alpha\": 2, 10: 3}; m[\"mid\"] = 4; m[\"zeta\"] = 5; m",
+        );
+        assert_eq!(map.to_string(), "{\"zeta\":5,\"alpha\":2,10:3,\"mid\":4}");
+
+        let keys = test_eval_program("keys({3: 0, (1, 2): 0, \"b\": 0, true: 0})");
+        assert_eq!(keys.to_string(), "[3,(1,2),\"b\",true]");
+
+        let values = test_eval_program("let m = {}; m[\"b\"] = 1; m[\"a\"] = 2; values(m)");
+        assert_eq!(values.to_string(), "[1,2]");
+
+        let stats = test_eval_program("keys(gc_stats())");
+        assert_eq!(
+            stats.to_string(),
+            "[\"environments\",\"arrays\",\"tuples\",\"hashmaps\",\"structs\",\"collections\",\"freed\",\"threshold\"]"
+        );
+
+        assert_eq!(test_eval_error("keys([1])").kind, TYPE_ERROR);
+        assert_eq!(test_eval_error("values({}, {})").kind, ARGUMENT_ERROR);
+    }
+
+    const BENCH_FIB: &str = "
+        fn fib(n) { if (n < 2) { n } else { fib(n - 1) + fib(n - 2) }; }
+        fib(25)
+    ";
+
+    const BENCH_CLOSURES: &str = "
+        fn adder(n) { fn(x) { x + n } }
+        fn tree(n) { if (n < 2) { adder(n)(1) } else { tree(n - 1) + tree(n - 2) }; }
+        tree(25)
+    ";
+
+    ///
+    ///  Run with `cargo test --release bench_ -- --ignored --nocapture`
+    ///
+    #[test]
+    #[ignore]
+    fn bench_recursive_calls() {
+        for (name, program, expected) in [
+            ("fib(25)", BENCH_FIB, 75025),
+            ("closures(25)", BENCH_CLOSURES, 196418),
+        ] {
+            let started = Instant::now();
+            assert_eq!(test_eval_program(program), Object::Integer(expected));
+            println!("{} took {:?}", name, started.elapsed());
+        }
+    }
+
+    #[test]
+    fn test_closures() {
+        let test_cases = vec![
+            TestCase {
+                test_str: "let adder = fn(x){fn(y) { x + y; };};\
+                           let a2 = adder(2);
+                           a2(10);",
+                val: Object::Integer(12),
+            },
+            TestCase {
+                test_str: "let x = 1; let f = fn(x) { x * 10 }; f(5) + x",
+                val: Object::Integer(51),
+            },
+            TestCase {
+                test_str: "let x = 1; let get = fn() { x }; let run = fn(x) { get() }; run(99)",
+                val: Object::Integer(1),
+            },
+            TestCase {
+                test_str: "let a = fn(x) { fn(y) { fn(z) { x + y + z } } }; a(1)(2)(3)",
+                val: Object::Integer(6),
+            },
+            TestCase {
+                test_str: "let make = fn() { let mut n = 0; fn() { n = n + 1 } };\
+                           let c = make(); c(); c(); c()",
+                val: Object::Integer(3),
+            },
+            TestCase {
+                test_str: "let make = fn() { let mut n = 0; fn() { n = n + 1 } };\
+                           let c1 = make(); let c2 = make(); c1(); c1(); c2()",
+                val: Object::Integer(1),
+            },
+            TestCase {
+                test_str: "fn account() {\
+                               let mut balance = 0;\
+                               [fn(x) { balance = balance + x }, fn() { balance }]\
+                           }\
+                           let acc = account(); acc[0](5); acc[0](7); acc[1]()",
+                val: Object::Integer(12),
+            },
+            TestCase {
+                test_str: "let x = 1; let f = fn(x) { x = 10 }; f(0); x",
+                val: Object::Integer(1),
+            },
+            TestCase {
+                test_str: "let x = 1; let f = fn() { let mut x = 2; x = 3 }; f(); x",
+                val: Object::Integer(1),
+            },
+            TestCase {
+                test_str: "let mut x = 1; let mut y = 2; x = y = 5; x + y",
+                val: Object::Integer(10),
+            },
+        ];
+
+        check_test_cases(test_cases);
+
+        let err = test_eval_error("let f = fn() { total = 1 }; f()");
+        assert_eq!(err.kind, NAME_ERROR);
+        assert_eq!(err.message, "undefined variable `total`");
+    }
+
+    #[test]
+    fn test_eval_resolved_locals() {
+        let test_cases = vec![
+            TestCase {
+                test_str: "fn f(x) { let x = x + 1; x } f(1)",
+                val: Object::Integer(2),
+            },
+            TestCase {
+                test_str: "let x = 10; fn f(y) { let z = y * 2; fn() { x + y + z } } f(1)()",
+                val: Object::Integer(13),
+            },
+            TestCase {
+                test_str: "fn f() { let g = fn() { later }; let later = 5; g() } f()",
+                val: Object::Integer(5),
+            },
+            TestCase {
+                test_str:
+                    "fn f() { try { throw \"e\" } catch (e) { let m = e; fn() { m } } } f()()",
+                val: Object::String(String::from("e")),
+            },
+            TestCase {
+                test_str: "let len = 3; fn f() { len } f()",
+                val: Object::Integer(3),
+            },
+            TestCase {
+                test_str:
+                    "fn f() { let a = 1; let g = fn() { a }; let mut a = a + 1; a = a * 10; [a, g()] } f()",
+                val: Object::array(vec![Object::Integer(20), Object::Integer(1)]),
+            },
+        ];
+
+        check_test_cases(test_cases);
+
+        let test_cases = [
+            "fn f() { let g = fn() { y }; g(); let y = 1; } f()",
+            "fn f() { x; let x = 1; }",
+        ];
+        for input in test_cases {
+            let err = test_eval_error(input);
+            assert_eq!(err.kind, NAME_ERROR, "{}", input);
+            assert!(
+                err.message.ends_with("used before its definition"),
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_eval_block_scopes() {
+        let test_cases = vec![
+            TestCase {
+                test_str: "let v = { let t = 2; t * 2 }; v",
+                val: Object::Integer(4),
+            },
+            TestCase {
+                test_str: "let x = 1; let y = if (true) { let x = 2; x }; x + y",
+                val: Object::Integer(3),
+            },
+            TestCase {
+                test_str: "fn f() { let n = 1; { let mut n = 2; n = n + 1 }; n } f()",
+                val: Object::Integer(1),
+            },
+            TestCase {
+                test_str: "let mut n = 1; { n = n + 5 }; n",
+                val: Object::Integer(6),
+            },
+            TestCase {
+                test_str:
+                    "let next = { let mut count = 0; fn() { count = count + 1 } }; next(); next()",
+                val: Object::Integer(2),
+            },
+            TestCase {
+                test_str: "{ fn g() { 5 } g() }",
+                val: Object::Integer(5),
+            },
+            TestCase {
+                test_str: "try { let t = 1; t } finally { let t = 2; }",
+                val: Object::Integer(1),
+            },
+            TestCase {
+                test_str: "let r = try { let t = 1; throw t } catch (e) { let t = 2; t }; r",
+                val: Object::Integer(2),
+            },
+            TestCase {
+                test_str: "let m = { (1, 2): 3 }; m[(1, 2)] + len({})",
+                val: Object::Integer(3),
+            },
+        ];
+        check_test_cases(test_cases);
+
+        let test_cases = [
+            ("if (true) { let v = 1 }; v", "v"),
+            ("let v = { let t = 1; t }; t", "t"),
+            ("fn f(c) { if (c) { let v = 1 }; v } f(false)", "v"),
+            ("try { let t = 1 } finally { t }", "t"),
+        ];
+        for (input, name) in test_cases {
+            let err = test_eval_error(input);
+            assert_eq!(err.kind, NAME_ERROR, "{}", input);
+            let message = format!("undefined variable `{}`", name);
+            assert!(err.message.starts_with(&message), "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_closure_scope_is_released() {
+        let counter =
+            test_eval_program("let make = fn() { let mut n = 0; fn() { n = n + 1 } }; make()");
+        let scope = match &counter {
+            Object::FunctionLiteral(_, _, env) => Rc::downgrade(env),
+            obj => panic!("Expected closure, found {}", obj),
+        };
+
+        assert!(scope.upgrade().is_some());
+        drop(counter);
+        assert!(scope.upgrade().is_none());
+    }
+
+    #[test]
+    fn test_eval_inbuilt_functions() {
+        let test_cases = vec![
+            TestCase {
+                test_str: "let x = \"cartman\"; len(x)",
+                val: Object::Integer(7),
+            },
+            TestCase {
+                test_str: "len(\"cartman\");",
+                val: Object::Integer(7),
+            },
+        ];
+
+        check_test_cases(test_cases);
+    }
+
+    #[test]
+    fn test_eval_arrays() {
+        let test_cases = vec![
+            TestCase {
+                test_str: "let x = [1, 2, 3]; x[2]",
+                val: Object::Integer(3),
+            },
+            TestCase {
+                test_str: "let x = [1, 2, [1, 3]]; x[2][0]",
+                val: Object::Integer(1),
+            },
+            TestCase {
+                test_str: "let x = [1, \"abc\", 32, 43]; x[1]",
+                val: Object::String(String::from("abc")),
+            },
+        ];
+
+        check_test_cases(test_cases);
+    }
+
+    #[test]
+    fn test_eval_slices_and_ranges() {
+        let test_cases = vec![
+            TestCase {
+                test_str: "let x = [1, 2, 3]; x[-1] * 10 + x[-3]",
+                val: Object::Integer(31),
+            },
+            TestCase {
+                test_str: "let s = \"crimson\"; s[0] + s[-1]",
+                val: Object::String("cn".to_string()),
+            },
+            TestCase {
+                test_str: "let s = \"crimson\"; s[2:] + s[:-4] + s[1:3]",
+                val: Object::String("imsoncriri".to_string()),
+            },
+            TestCase {
+                test_str: "let x = [1, 2, 3, 4]; let y = x[1:3]; y[0] = 9; x[1]",
+                val: Object::Integer(2),
+            },
+            TestCase {
+                test_str: "len([1, 2][5:]) + len([1, 2][-10:1]) * 10",
+                val: Object::Integer(10),
+            },
+            TestCase {
+                test_str: "let r = 0..10; len(r) * 100 + r[3] * 10 + r[-1]",
+                val: Object::Integer(1039),
+            },
+            TestCase {
+                test_str: "len(1..=3) + len(5..1)",
+                val: Object::Integer(3),
+            },
+            TestCase {
+                test_str: "fn add(a, b, c) { a + b + c } add(...(1..4))",
+                val: Object::Integer(6),
+            },
+            TestCase {
+                test_str: "len(-9223372036854775807..0)",
+                val: Object::Integer(i64::MAX),
+            },
+        ];
+        check_test_cases(test_cases);
+
+        let display = |input| test_eval_program(input).to_string();
+        assert_eq!(display("[1, 2, 3, 4][:-1]"), "[1,2,3]");
+        assert_eq!(display("(1, 2, 3)[1:]"), "(2,3)");
+        assert_eq!(display("[1, 2][:]"), "[1,2]");
+        assert_eq!(display("let n = 2; 1..n + 1"), "1..3");
+        assert_eq!(display("(0..10)[2:-2]"), "2..8");
+        assert_eq!(display("map(1..=3, fn(x) { x * x })"), "[1,4,9]");
+        assert_eq!(display("map(\"ab\", fn(c) { c + c })"), "[\"aa\",\"bb\"]");
+        assert_eq!(display("map((), len)"), "[]");
+
+        let test_cases = [
+            ("[1, 2][2]", INDEX_ERROR),
+            ("\"\"[-1]", INDEX_ERROR),
+            ("(0..3)[3]", INDEX_ERROR),
+            ("[1][\"a\":]", TYPE_ERROR),
+            ("5[1:2]", TYPE_ERROR),
+            ("0..\"a\"", TYPE_ERROR),
+            ("0..=9223372036854775807", OVERFLOW_ERROR),
+            ("map(1, len)", TYPE_ERROR),
+            ("map([1], fn(x) { x[0] })", TYPE_ERROR),
+        ];
+        for (input, kind) in test_cases {
+            assert_eq!(test_eval_error(input).kind, kind, "{}", input);
+        }
+
+        let err = test_eval_error("let x = [1, 2];\nx[-3]");
+        assert_eq!(
+            err.message,
+            "array index -3 out of range for array of length 2"
+        );
+        assert_eq!(err.position, Some(Position { line: 2, column: 2 }));
+    }
+
+    #[test]
+    fn test_eval_constants() {
+        let test_cases = vec![
+            TestCase {
+                test_str: "const limit = 3; fn f() { limit * 2 } f()",
+                val: Object::Integer(6),
+            },
+            TestCase {
+                test_str: "const items = [1]; items[0] = 2; items[0]",
+                val: Object::Integer(2),
+            },
+            TestCase {
+                test_str: "const [a, b] = [1, 2]; fn f() { const a = 10; a + b } f()",
+                val: Object::Integer(12),
+            },
+        ];
+        check_test_cases(test_cases);
+
+        let test_cases = [
+            ("const a = 1; a = 2", "cannot assign to constant `a`"),
+            (
+                "const a = 1; fn f() { a = 2 } f()",
+                "cannot assign to constant `a`",
+            ),
+            ("const a = 1; let a = 2;", "cannot redeclare constant `a`"),
+            (
+                "const {a} = {\"a\": 1}; let [b, a] = [1, 2];",
+                "cannot redeclare constant `a`",
+            ),
+            ("const a = 1; const a = 2;", "cannot redeclare constant `a`"),
+        ];
+        for (input, message) in test_cases {
+            let err = test_eval_error(input);
+            assert_eq!(err.kind, TYPE_ERROR, "{}", input);
+            assert_eq!(err.message, message, "{}", input);
+        }
+
+        let err = test_eval_error("const a = 1;\nfn f() {\n  a = 2\n}\nf()");
+        assert_eq!(err.position, Some(Position { line: 3, column: 5 }));
+    }
+
+    #[test]
+    fn test_eval_mutability() {
+        let test_cases = vec![
+            TestCase {
+                test_str: "let mut a = 1; a = a + 1; a",
+                val: Object::Integer(2),
+            },
+            TestCase {
+                test_str: "let a = 1; let mut a = a + 1; fn f() { a = a * 10 } f(); a",
+                val: Object::Integer(20),
+            },
+            TestCase {
+                test_str: "let mut [a, {b}] = [1, {\"b\": 2}]; a = b = 3; a + b",
+                val: Object::Integer(6),
+            },
+            TestCase {
+                test_str: "let items = [1]; items[0] = 2; items[0]",
+                val: Object::Integer(2),
+            },
+            TestCase {
+                test_str: "let a = 1; let a = 2; a",
+                val: Object::Integer(2),
+            },
+            TestCase {
+                test_str: "fn f(n) { n = n + 1; n } f(1)",
+                val: Object::Integer(2),
+            },
+        ];
+        check_test_cases(test_cases);
+
+        let test_cases = [
+            ("let a = 1; a = 2", "a"),
+            ("let a = 1; fn f() { a = 2 } f()", "a"),
+            ("let mut a = 1; let a = 2; a = 3", "a"),
+            ("let [a, b] = [1, 2]; b = 3", "b"),
+            ("fn f() { let a = 1; a = 2 } f()", "a"),
+        ];
+        for (input, name) in test_cases {
+            let err = test_eval_error(input);
+            assert_eq!(err.kind, TYPE_ERROR, "{}", input);
+            assert_eq!(
+                err.message,
+                format!(
+                    "cannot assign to immutable variable `{}`, declare it with `let mut`",
+                    name
+                ),
+                "{}",
+                input
+            );
+        }
+
+        let err = test_eval_error("let a = 1;\nfn f() {\n  a = 2\n}\nf()");
+        assert_eq!(err.position, Some(Position { line: 3, column: 5 }));
+    }
+
+    #[test]
+    fn test_eval_lambdas() {
+        let test_cases = vec![
+            TestCase {
+                test_str: "map([1, 2, 3], |x| x * 2) |> sum",
+                val: Object::Integer(12),
+            },
+            TestCase {
+                test_str: "[1, 2, 3].map((x) => x + 1).sum()",
+                val: Object::Integer(9),
+            },
+            TestCase {
+                test_str: "let add = (a, b = 10) => a + b; add(1) + add(1, b: 2)",
+                val: Object::Integer(14),
+            },
+            TestCase {
+                test_str: "(|| 7)() + (() => 8)()",
+                val: Object::Integer(15),
+            },
+            TestCase {
+                test_str: "let adder = |x| |y| x + y; adder(1)(2)",
+                val: Object::Integer(3),
+            },
+            TestCase {
+                test_str: "(|[x, y], ...rest| x + y + len(rest))([1, 2], 3, 4)",
+                val: Object::Integer(5),
+            },
+            TestCase {
+                test_str: "let mut n = 3; let f = (x) => x * n; n = 4; f(2)",
+                val: Object::Integer(8),
+            },
+            TestCase {
+                test_str: "let twice = |f| f >> f; twice(|x| x * 3)(1)",
+                val: Object::Integer(9),
+            },
+        ];
+        check_test_cases(test_cases);
+
+        let err = test_eval_error("let f = (x) => x + \"a\";\nf(1)");
+        assert_eq!(err.kind, TYPE_ERROR);
+        assert_eq!(err.trace[0].function, "f");
+    }
+
+    #[test]
+    fn test_eval_pipelines() {
+        let test_cases = vec![
+            TestCase {
+                test_str: "[1, 2, 3] |> map(fn(x) { x * 2 }) |> sum",
+                val: Object::Integer(12),
+            },
+            TestCase {
+                test_str: "fn add(a, b) { a - b } 10 |> add(3)",
+                val: Object::Integer(7),
+            },
+            TestCase {
+                test_str:
+                    "let double = fn(x) { x * 2 }; let inc = fn(x) { x + 1 }; (double >> inc)(5)",
+                val: Object::Integer(11),
+            },
+            TestCase {
+                test_str:
+                    "let double = fn(x) { x * 2 }; let inc = fn(x) { x + 1 }; 5 |> inc >> double",
+                val: Object::Integer(12),
+            },
+            TestCase {
+                test_str: "let inc = fn(x) { x + 1 }; map(0..3, inc >> inc >> inc) |> sum",
+                val: Object::Integer(12),
+            },
+            TestCase {
+                test_str: "fn f(a, b = 1) { [a, b] } let g = f >> len; g(1, b: 2)",
+                val: Object::Integer(2),
+            },
+            TestCase {
+                test_str: "[1, 2] |> len >> fn(n) { n * 10 }",
+                val: Object::Integer(20),
+            },
+            TestCase {
+                test_str: "3 |> [1, 2].push() |> len",
+                val: Object::Integer(3),
+            },
+            TestCase {
+                test_str: "(0..5).sum() + (1, 2).sum()",
+                val: Object::Integer(13),
+            },
+            TestCase {
+                test_str: "16 >> 2",
+                val: Object::Integer(4),
+            },
+        ];
+        check_test_cases(test_cases);
+
+        let test_cases = [
+            (
+                "len >> 1",
+                "unsupported operand types for >>: builtin function and integer",
+            ),
+            ("1 |> 2", "integer is not a function"),
+            ("sum([1, \"a\"])", "Expected integers to sum, found string"),
+            (
+                "sum(\"ab\")",
+                "Expected an array, tuple or range argument for sum, found string",
+            ),
+        ];
+        for (input, message) in test_cases {
+            let err = test_eval_error(input);
+            assert_eq!(err.kind, TYPE_ERROR, "{}", input);
+            assert_eq!(err.message, message, "{}", input);
+        }
+
+        let composed = test_eval_program("let f = len >> upper; [f, len]");
+        assert_eq!(
+            composed.to_string(),
+            "[<builtin len> >> <builtin upper>,<builtin len>]"
+        );
+        let err = test_eval_error("throw len >> len");
+        assert_eq!(err.message, "<builtin len> >> <builtin len>");
+
+        let err = test_eval_error("fn fail(x) { x + \"a\" }\nlet f = len >> fail;\nf([1])");
+        assert_eq!(err.trace[0].function, "fail");
+        assert_eq!(err.trace[0].position, Position { line: 3, column: 2 });
+    }
+
+    #[test]
+    fn test_eval_destructuring() {
+        let test_cases = vec![
+            TestCase {
+                test_str: "let [a, b, ...rest] = [1, 2, 3, 4]; a * 100 + b * 10 + len(rest)",
+                val: Object::Integer(122),
+            },
+            TestCase {
+                test_str: "let person = {\"name\": \"Ada\", \"age\": 36};
+                           let {name, age} = person;
+                           name + \" \" + [\"x\"][age - 36]",
+                val: Object::String(String::from("Ada x")),
+            },
+            TestCase {
+                test_str: "let (q, (r, _)) = (1, (2, 3)); q + r",
+                val: Object::Integer(3),
+            },
+            TestCase {
+                test_str: "struct Point { x, y } let Point { x, y: py } = Point { x: 1, y: 2 }; x + py",
+                val: Object::Integer(3),
+            },
+            TestCase {
+                test_str: "fn divmod(a, b) { [a / b, a - a / b * b] } let [d, m] = divmod(17, 5); d * 10 + m",
+                val: Object::Integer(32),
+            },
+            TestCase {
+                test_str: "fn add([a, b], {c}, d = a + b + c) { d } add([1, 2], {\"c\": 3})",
+                val: Object::Integer(6),
+            },
+            TestCase {
+                test_str: "let sum = fn((a, b), ...more) { a + b + len(more) }; sum((1, 2), 3, 4)",
+                val: Object::Integer(5),
+            },
+            TestCase {
+                test_str: "map([(1, 2), (3, 4)], fn((a, b)) { a * b })[1]",
+                val: Object::Integer(12),
+            },
+            TestCase {
+                test_str: "fn f() { let [x, ...] = [7, 8]; fn() { x } } f()()",
+                val: Object::Integer(7),
+            },
+            TestCase {
+                test_str: "fn f([_], [_]) { 1 } f([1], [2])",
+                val: Object::Integer(1),
+            },
+            TestCase {
+                test_str: "(|[_], [_]| 1)([1], [2])",
+                val: Object::Integer(1),
+            },
+            TestCase {
+                test_str: "fn f([_], [_]) { let c = 5; c } f([1], [2])",
+                val: Object::Integer(5),
+            },
+            TestCase {
+                test_str: "fn f([a], {b}, [_]) { let c = a + b; c } f([1], {\"b\": 2}, [3])",
+                val: Object::Integer(3),
+            },
+        ];
+        check_test_cases(test_cases);
+
+        let test_cases = [
+            (
+                "let [a, b] = [1];",
+                MATCH_ERROR,
+                "[1] does not match the pattern [a,b]",
+            ),
+            (
+                "let {name} = {\"age\": 1};",
+                MATCH_ERROR,
+                "{\"age\":1} does not match the pattern {\"name\":name}",
+            ),
+            (
+                "fn f([a, b]) { a } f(1)",
+                MATCH_ERROR,
+                "1 does not match the pattern [a,b]",
+            ),
+            (
+                "fn f([a, b]) { a } f()",
+                ARGUMENT_ERROR,
+                "f missing required argument `[a,b]`",
+            ),
+        ];
+        for (input, kind, message) in test_cases {
+            let err = test_eval_error(input);
+            assert_eq!(err.kind, kind, "{}", input);
+            assert_eq!(err.message, message, "{}", input);
+        }
+
+        let err = test_eval_error("let x = 1;\nlet (a, b) = x;");
+        assert_eq!(err.position, Some(Position { line: 2, column: 1 }));
+        let err = test_eval_error("fn f((a, b)) { a }\nf(1)");
+        assert_eq!(err.position, Some(Position { line: 2, column: 2 }));
+    }
+
+    #[test]
+    fn test_eval_match() {
+        let test_cases = vec![
+            TestCase {
+                test_str: "enum Shape { Circle(r), Rect(w, h), Empty }
+                           fn area(s) {
+                               match (s) {
+                                   Shape.Circle(r) => 3 * r * r,
+                                   Shape.Rect(w, h) if w == h => 1000 + w * h,
+                                   Shape.Rect(w, h) => w * h,
+                                   Shape.Empty => 0,
+                               }
+                           }
+                           area(Shape.Circle(2)) + area(Shape.Rect(2, 3)) + area(Shape.Rect(1, 1)) + area(Shape.Empty)",
+                val: Object::Integer(1019),
+            },
+            TestCase {
+                test_str: "fn sum(items) { match (items) { [] => 0, [first, ...rest] => first + sum(rest) } }
+                           sum([1, 2, 3, 4])",
+                val: Object::Integer(10),
+            },
+            TestCase {
+                test_str: "fn describe(x) {
+                               match (x) {
+                                   0 => \"zero\",
+                                   -1 => \"minus one\",
+                                   \"hi\" => \"greeting\",
+                                   true => \"yes\",
+                                   null => \"nothing\",
+                                   (a, b) => \"pair\",
+                                   [_, ...] => \"array\",
+                                   _ => \"other\"
+                               }
+                           }
+                           describe(0) + describe(-1) + describe(\"hi\") + describe(true) + describe(null)
+                               + describe((1, 2)) + describe([1]) + describe([]) + describe(3)",
+                val: Object::String(String::from(
+                    "zerominus onegreetingyesnothingpairarrayotherother",
+                )),
+            },
+            TestCase {
+                test_str: "let person = {\"name\": \"Ada\", \"age\": 36, \"langs\": [\"en\"]};
+                           match (person) {
+                               {name, \"age\": 40} => \"forty\",
+                               {name, langs: [first]} => name + \" speaks \" + first,
+                           }",
+                val: Object::String(String::from("Ada speaks en")),
+            },
+            TestCase {
+                test_str: "struct Point { x, y }
+                           match (Point { x: 1, y: 2 }) {
+                               Point { x: 0 } => 0,
+                               Point { x, y } => x * 10 + y,
+                           }",
+                val: Object::Integer(12),
+            },
+            TestCase {
+                test_str: "let x = 5; match (x) { x if x > 3 => { let y = x * 2; y } _ => 0 }",
+                val: Object::Integer(10),
             },
             TestCase {
-                test_str: "!false",
-                val: Object::Bool(true),
+                test_str: "enum Tree { Leaf(v), Node(l, r) }
+                           fn total(t) { match (t) { Tree.Leaf(v) => v, Tree.Node(l, r) => total(l) + total(r) } }
+                           total(Tree.Node(Tree.Leaf(1), Tree.Node(Tree.Leaf(2), Tree.Leaf(3))))",
+                val: Object::Integer(6),
             },
             TestCase {
-                test_str: "-1",
-                val: Object::Integer(-1),
+                test_str: "enum Color { Red, Green } let c = Color.Green; match (c) { Color.Red => 1, Color.Green => 2 }",
+                val: Object::Integer(2),
             },
         ];
-
         check_test_cases(test_cases);
+
+        let display = |input| test_eval_program(input).to_string();
+        assert_eq!(
+            display("enum Shape { Circle(r), Empty } [Shape.Circle([1]), Shape.Empty]"),
+            "[Shape.Circle([1]),Shape.Empty]"
+        );
+        assert_eq!(
+            display("enum Shape { Circle(r), Empty } Shape"),
+            "enum Shape { Circle(r),Empty }"
+        );
+
+        let test_cases = [
+            (
+                "match (3) { 1 => 1, 2 => 2 }",
+                MATCH_ERROR,
+                "no match arm matches 3",
+            ),
+            (
+                "match (1) { x if x => 1 }",
+                TYPE_ERROR,
+                "expected boolean guard in match arm, found integer",
+            ),
+            (
+                "enum Shape { Circle(r) } Shape.Square",
+                ATTRIBUTE_ERROR,
+                "Shape has no variant `Square`",
+            ),
+            (
+                "enum Shape { Circle(r) } Shape.Circle(1, 2)",
+                ARGUMENT_ERROR,
+                "Shape.Circle takes 1 value but 2 were given",
+            ),
+            (
+                "enum Shape { Circle(r) } Shape.Circle",
+                ARGUMENT_ERROR,
+                "Shape.Circle takes 1 value but 0 were given",
+            ),
+            (
+                "enum Shape { Circle(r) } match (1) { Shape.Circle(a, b) => 1 }",
+                TYPE_ERROR,
+                "Shape.Circle holds 1 value but the pattern has 2",
+            ),
+            (
+                "enum Shape { Rect(w, h) } Shape.Rect(1)",
+                ARGUMENT_ERROR,
+                "Shape.Rect takes 2 values but 1 was given",
+            ),
+            (
+                "enum Shape { Rect(w, h) } match (1) { Shape.Rect(a) => 1 }",
+                TYPE_ERROR,
+                "Shape.Rect holds 2 values but the pattern has 1",
+            ),
+            (
+                "struct P { x } match (1) { P { y } => 1 }",
+                ATTRIBUTE_ERROR,
+                "P has no field `y`",
+            ),
+            (
+                "let P = 1; match (1) { P { y } => 1 }",
+                TYPE_ERROR,
+                "`P` is not a struct, found integer",
+            ),
+            (
+                "match ([1]) { [a] => a }; a",
+                NAME_ERROR,
+                "undefined variable `a`",
+            ),
+        ];
+        for (input, kind, message) in test_cases {
+            let err = test_eval_error(input);
+            assert_eq!(err.kind, kind, "{}", input);
+            assert_eq!(err.message, message, "{}", input);
+        }
+
+        let err = test_eval_error("let v = 2;\nmatch (v) { 1 => 1 }");
+        assert_eq!(err.position, Some(Position { line: 2, column: 1 }));
     }
 
     #[test]
-    fn test_eval_infix() {
+    fn test_eval_methods() {
         let test_cases = vec![
             TestCase {
-                test_str: "10 > 20",
-                val: Object::Bool(false),
-            },
-            TestCase {
-                test_str: "-1 + 0",
-                val: Object::Integer(-1),
+                test_str: "let arr = [1, 2, 3]; arr.push(4); arr.len()",
+                val: Object::Integer(4),
             },
             TestCase {
-                test_str: "5 > 3",
-                val: Object::Bool(true),
+                test_str: "[1, 2].push(3).map(fn(x) { x * 2 })[2]",
+                val: Object::Integer(6),
             },
             TestCase {
-                test_str: "4 < 2",
-                val: Object::Bool(false),
+                test_str: "\"  Crimson \".trim().upper()",
+                val: Object::String(String::from("CRIMSON")),
             },
             TestCase {
-                test_str: "5 == 3",
-                val: Object::Bool(false),
+                test_str: "\"a,b,c\".split(\",\").len()",
+                val: Object::Integer(3),
             },
             TestCase {
-                test_str: "4 != 2",
-                val: Object::Bool(true),
+                test_str: "let m = {\"a\": 1, \"b\": 2}; m.keys()[1].upper() + m.keys()[0]",
+                val: Object::String(String::from("Ba")),
             },
             TestCase {
-                test_str: "(2*2 + 1) == 5",
-                val: Object::Bool(true),
+                test_str: "(1..4).map(fn(x) { x * x })[2] + (1, 2).len()",
+                val: Object::Integer(11),
             },
             TestCase {
-                test_str: "(2 + 3)*2 == 10",
-                val: Object::Bool(true),
+                test_str: "struct Counter { n, fn inc(self, by) { self.n = self.n + by; self } }
+                           let c = Counter { n: 0 };
+                           c.inc(2).inc(3);
+                           c.n",
+                val: Object::Integer(5),
             },
             TestCase {
-                test_str: "\"ab\" + \"cd\"",
-                val: Object::String(String::from("abcd")),
+                test_str: "struct P { x, fn scaled(self, k) { P { x: self.x * k } } }
+                           P { x: 2 }.scaled(3).scaled(k: 4).x",
+                val: Object::Integer(24),
             },
             TestCase {
-                test_str: "\"ab\" + \"cd\" + \"ef\"",
-                val: Object::String(String::from("abcdef")),
+                test_str: "struct Op { run } Op { run: fn(x) { x + 1 } }.run(1)",
+                val: Object::Integer(2),
             },
         ];
-
         check_test_cases(test_cases);
+
+        let test_cases = [
+            (
+                "[1].upper()",
+                ATTRIBUTE_ERROR,
+                "array has no method `upper`",
+            ),
+            ("1.len()", ATTRIBUTE_ERROR, "integer has no method `len`"),
+            (
+                "struct P { x } P { x: 1 }.y()",
+                ATTRIBUTE_ERROR,
+                "P has no method `y`",
+            ),
+            (
+                "struct P { fn f(self, a) { a } } P {}.f()",
+                ARGUMENT_ERROR,
+                "P.f missing required argument `a`",
+            ),
+        ];
+        for (input, kind, message) in test_cases {
+            let err = test_eval_error(input);
+            assert_eq!(err.kind, kind, "{}", input);
+            assert_eq!(err.message, message, "{}", input);
+        }
+
+        let err = test_eval_error("struct P { fn f(self) { 1 / 0 } }\nP {}.f()");
+        assert_eq!(err.trace[0].function, "P.f");
+        assert_eq!(err.trace[0].position, Position { line: 2, column: 5 });
     }
 
     #[test]
-    fn test_eval_if_expr() {
+    fn test_eval_structs() {
         let test_cases = vec![
             TestCase {
-                test_str: "if (10 > 20) {20} else {10}",
-                val: Object::Integer(10),
+                test_str: "struct Point { x, y } let p = Point { y: 2, x: 1 }; p.x * 10 + p.y",
+                val: Object::Integer(12),
             },
             TestCase {
-                test_str: "if (21 > 20) {20} else {10}",
-                val: Object::Integer(20),
+                test_str: "let x = 3; let p = Point { x, y: x + 1 }; struct Point { x, y } p.y",
+                val: Object::Integer(4),
             },
             TestCase {
-                test_str: "if (21 > 20) {20} ",
+                test_str:
+                    "struct Box { value } let a = Box { value: 1 }; let b = a; b.value = 5; a.value",
+                val: Object::Integer(5),
+            },
+            TestCase {
+                test_str: "struct Node { value, next }\
+                           let list = Node { value: 1, next: Node { value: 2, next: null } };\
+                           list.next.value = list.next.value * 10; list.next.value",
                 val: Object::Integer(20),
             },
             TestCase {
-                test_str: "if (21 < 20) {20} ",
-                val: Object::Null,
+                test_str: "fn make(n) { struct Pair { a, b } Pair { a: n, b: [n] } } make(7).b[0]",
+                val: Object::Integer(7),
             },
             TestCase {
-                test_str: "if (21 > 20) {let x = 30; 20} ",
-                val: Object::Integer(20),
+                test_str:
+                    "struct P { a } let p = P { a: 1 }; let c = copy(p); c.a = 2; p.a * 10 + c.a",
+                val: Object::Integer(12),
             },
         ];
-
         check_test_cases(test_cases);
+
+        let display = |input| test_eval_program(input).to_string();
+        assert_eq!(
+            display("struct Point { x, y } Point { x: 1, y: \"a\" }"),
+            "Point { x: 1,y: \"a\" }"
+        );
+        assert_eq!(
+            display("struct Point { x, y } Point"),
+            "struct Point { x,y }"
+        );
+        assert_eq!(
+            display("struct Node { next } let n = Node { next: null }; n.next = n; n"),
+            "Node { next: ... }"
+        );
+
+        let test_cases = [
+            (
+                "struct P { x } P { x: 1 }.y",
+                ATTRIBUTE_ERROR,
+                "P has no field `y`",
+            ),
+            (
+                "struct P { x } P { x: 1, y: 2 }",
+                ATTRIBUTE_ERROR,
+                "P has no field `y`",
+            ),
+            (
+                "struct P { x } let p = P { x: 1 }; p.z = 1",
+                ATTRIBUTE_ERROR,
+                "P has no field `z`",
+            ),
+            ("[1].x", ATTRIBUTE_ERROR, "array has no field `x`"),
+            (
+                "struct P { x, y } P { y: 1 }",
+                TYPE_ERROR,
+                "P is missing field `x`",
+            ),
+            (
+                "let P = 1; P { x: 1 }",
+                TYPE_ERROR,
+                "`P` is not a struct, found integer",
+            ),
+            (
+                "Missing { x: 1 }",
+                NAME_ERROR,
+                "undefined variable `Missing`",
+            ),
+        ];
+        for (input, kind, message) in test_cases {
+            let err = test_eval_error(input);
+            assert_eq!(err.kind, kind, "{}", input);
+            assert_eq!(err.message, message, "{}", input);
+        }
+
+        let err = test_eval_error("struct P { x }\nlet p = P { x: 1 };\np.y");
+        assert_eq!(err.position, Some(Position { line: 3, column: 2 }));
+        let caught =
+            test_eval_program("struct P { x } try { P { x: 1 }.y } catch (e) { e[\"kind\"] }");
+        assert_eq!(caught, Object::String(ATTRIBUTE_ERROR.to_string()));
     }
 
     #[test]
-    fn test_eval_let_statements() {
+    fn test_eval_undefined_variable() {
+        let test_cases = [
+            ("foo", "undefined variable `foo`", 1, 1),
+            (
+                "let food = 1; foo + 1",
+                "undefined variable `foo`, did you mean `food`?",
+                1,
+                15,
+            ),
+            (
+                "let counter = 1;\nlet f = fn(x) { x + countr };\nf(1)",
+                "undefined variable `countr`, did you mean `counter`?",
+                2,
+                21,
+            ),
+            (
+                "lne(\"abc\")",
+                "undefined variable `lne`, did you mean `len`?",
+                1,
+                1,
+            ),
+            (
+                "let f = fn(value) { valeu }; f(1)",
+                "undefined variable `valeu`, did you mean `value`?",
+                1,
+                21,
+            ),
+            (
+                "let apple = 1; banana",
+                "undefined variable `banana`",
+                1,
+                16,
+            ),
+        ];
+
+        for (input, message, line, column) in test_cases {
+            let err = test_eval_error(input);
+            assert_eq!(err.message, message);
+            assert_eq!(err.position, Some(Position { line, column }));
+        }
+    }
+
+    #[test]
+    fn test_eval_runtime_errors() {
+        let test_cases = [
+            ("1 + \"a\"", TYPE_ERROR),
+            ("-true", TYPE_ERROR),
+            ("if (1) { 2 }", TYPE_ERROR),
+            ("let x = 5; x(1)", TYPE_ERROR),
+            ("let x = [1, 2]; x[2]", INDEX_ERROR),
+            ("[][0]", INDEX_ERROR),
+            ("[1][-2]", INDEX_ERROR),
+            ("let a = []; a[-1]", INDEX_ERROR),
+            ("{[1]: 1}", TYPE_ERROR),
+            ("let f = fn(a, b) { a }; f(1)", ARGUMENT_ERROR),
+            ("len(1)", TYPE_ERROR),
+            ("len(1, 2)", ARGUMENT_ERROR),
+            ("10 / 0", ZERO_DIVISION_ERROR),
+            ("9223372036854775807 + 1", OVERFLOW_ERROR),
+            ("1 << 64", OVERFLOW_ERROR),
+            ("throw \"boom\"", ERROR),
+            ("throw error(\"bad input\", \"ValueError\")", "ValueError"),
+        ];
+
+        for (input, kind) in test_cases {
+            assert_eq!(test_eval_error(input).kind, kind, "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_eval_try_catch() {
         let test_cases = vec![
             TestCase {
-                test_str: "let x = 10; if (x > 20) {20} else {10}",
-                val: Object::Integer(10),
+                test_str: "try { 1 } catch (e) { 2 }",
+                val: Object::Integer(1),
             },
             TestCase {
-                test_str: "let y = 20; if (21 > y) {20} else {10}",
-                val: Object::Integer(20),
+                test_str: "try { 1 / 0 } catch (e) { 2 }",
+                val: Object::Integer(2),
             },
             TestCase {
-                test_str: "let z = 30; z*z; ",
-                val: Object::Integer(900),
+                test_str: "try { throw \"boom\"; 1 } catch (e) { e }",
+                val: Object::String(String::from("boom")),
             },
             TestCase {
-                test_str: "let a = 30; let b = 40; a + b",
-                val: Object::Integer(70),
+                test_str: "try { throw 5 } catch (e) { [e, is_error(e)] }",
+                val: Object::array(vec![Object::Integer(5), Object::Bool(false)]),
             },
-        ];
-
-        check_test_cases(test_cases);
-    }
-
-    #[test]
-    fn test_eval_functions() {
-        let test_cases = vec![
             TestCase {
-                test_str: "let sum = fn(x, y){ x + y;}; \
-                                  sum(10, 20);",
-                val: Object::Integer(30),
+                test_str: "let a = [1]; try { throw a } catch (e) { push(e, 2) }; a",
+                val: Object::array(vec![Object::Integer(1), Object::Integer(2)]),
             },
             TestCase {
-                test_str: "let square = fn(x){x*x}; \
-                                  square(10)",
-                val: Object::Integer(100),
+                test_str: "try { throw error(\"x\") } catch (e) { keys(e) }",
+                val: Object::array(vec![
+                    Object::String(String::from("message")),
+                    Object::String(String::from("kind")),
+                    Object::String(String::from("trace")),
+                ]),
             },
             TestCase {
-                test_str: "let gt = fn(x, y){ \
-                                                if (!(x > y)) {\
-                                                        x*x\
-                                                } else {\
-                                                        y\
-                                                };\
-                                           }; \
-                                  gt(3, 2)",
-                val: Object::Integer(2),
+                test_str: "try { 1 / 0 } catch (e) { [len(e), e.values()[1]] }",
+                val: Object::array(vec![
+                    Object::Integer(3),
+                    Object::String(String::from("ZeroDivisionError")),
+                ]),
             },
             TestCase {
-                test_str: "let gt = fn(x, y){ \
-                                                if (x > y) \
-                                                    {x*x} \
-                                                else {\
-                                                     y\
-                                                };\
-                                            }; \
-                                  gt(3, 2)",
-                val: Object::Integer(9),
+                test_str: "try { missing } catch (e) { e[\"kind\"] }",
+                val: Object::String(String::from("NameError")),
             },
             TestCase {
-                test_str: "let fact = fn(x) {\
-                                                if (x > 1) {\
-                                                    x*fact(x - 1);\
-                                                } else {\
-                                                    x\
-                                                };\
-                                             }; \
-                                  fact(8);",
-                val: Object::Integer(40320),
+                test_str: "let f = fn(x) { if (x > 2) { throw error(\"too big\", \"RangeError\") } else { x }; };\
+                           try { f(1) + f(5) } catch (err) { err[\"kind\"] + \": \" + err[\"message\"] }",
+                val: Object::String(String::from("RangeError: too big")),
             },
             TestCase {
-                test_str: "let sum = fn(x,y){x + y;};\
-                                  let sqr = fn(x){let z = sum(x, x); z*z;};\
-                                  let z = sum(2, 3) + sqr(2);\
-                                  z;",
-                val: Object::Integer(21),
+                test_str: "let r = try { 1 } finally { 2 }; r",
+                val: Object::Integer(1),
             },
             TestCase {
-                test_str: "fn(x, y, z){x + y + z}(1, 2, 3);",
-                val: Object::Integer(6),
+                test_str: "try { try { throw 1 } finally { 2 } } catch (e) { e }",
+                val: Object::Integer(1),
             },
             TestCase {
-                test_str: "let a = \"wx\";\
-                                let b = \"yz\";\
-                                let z = a + b;\
-                                z;",
-                val: Object::String(String::from("wxyz")),
+                test_str: "try { try { throw \"inner\" } catch (e) { throw e } } catch (e) { e }",
+                val: Object::String(String::from("inner")),
+            },
+            TestCase {
+                test_str: "try { throw \"a\" } catch (e) { 1 } finally { 2 }",
+                val: Object::Integer(1),
+            },
+            TestCase {
+                test_str: "is_error(error(\"x\"))",
+                val: Object::Bool(true),
+            },
+            TestCase {
+                test_str: "is_error(\"x\")",
+                val: Object::Bool(false),
+            },
+            TestCase {
+                test_str: "let e = try { len(1) } catch (e) { e }; is_error(e)",
+                val: Object::Bool(true),
             },
         ];
 
         check_test_cases(test_cases);
+
+        let err = test_eval_error("try { 1 } catch (e) { 2 } finally { throw \"cleanup\" }");
+        assert_eq!(err.message, "cleanup");
+
+        let err = test_eval_error("try { throw \"a\" } catch (e) { e }; e");
+        assert_eq!(err.kind, NAME_ERROR);
     }
 
     #[test]
-    fn test_closures() {
-        let _test_cases = vec![TestCase {
-            test_str: "let adder = fn(x){fn(x,y) { x + y; };};\
-                           let a2 = adder(2);
-                           a2(10);",
-            val: Object::Integer(12),
-        }];
+    fn test_eval_error_trace() {
+        let err = test_eval_error(
+            "let inner = fn(x) { x / 0 };\nlet outer = fn(x) { inner(x) + 1 };\nouter(3)",
+        );
+        assert_eq!(err.kind, ZERO_DIVISION_ERROR);
+        assert_eq!(
+            err.trace,
+            vec![
+                Frame {
+                    function: String::from("outer"),
+                    position: Position { line: 3, column: 6 },
+                },
+                Frame {
+                    function: String::from("inner"),
+                    position: Position {
+                        line: 2,
+                        column: 26
+                    },
+                },
+            ]
+        );
+
+        let trace = test_eval_program(
+            "let f = fn() { error(\"x\") }; let g = fn() { f() }; g()[\"trace\"]",
+        );
+        assert_eq!(
+            trace,
+            Object::array(vec![
+                Object::String(String::from("g at line 1, column 53")),
+                Object::String(String::from("f at line 1, column 46")),
+            ])
+        );
+
+        let err = test_eval_error("throw \"boom\"");
+        assert_eq!(err.position, Some(Position { line: 1, column: 1 }));
+        assert!(err.trace.is_empty());
+
+        // An error in a default value happens inside the call it belongs to
+        let err = test_eval_error("fn f(a = 1 / 0) { a }\nf()");
+        assert_eq!(
+            err.trace,
+            vec![Frame {
+                function: String::from("f"),
+                position: Position { line: 2, column: 2 },
+            }]
+        );
     }
 
     #[test]
-    fn test_eval_inbuilt_functions() {
+    fn test_eval_traceback() {
+        let err = test_eval_error(
+            "let inner = fn(x) { x / 0 };\nlet outer = fn(x) { inner(x) + 1 };\nouter(3)",
+        );
+        assert_eq!(
+            err.traceback(),
+            "Traceback (most recent call last):\n  \
+             in outer at line 3, column 6\n  \
+             in inner at line 2, column 26\n\
+             ZeroDivisionError at line 1, column 23: division by zero in 3 / 0"
+        );
+
+        let err = test_eval_error("let x = 1;\n-\"a\"");
+        assert_eq!(
+            err.traceback(),
+            "TypeError at line 2, column 1: unsupported operand type for -: string"
+        );
+    }
+
+    #[test]
+    fn test_eval_recursion_limit() {
+        let (traceback, kind, position, depth) = std::thread::Builder::new()
+            .stack_size(256 * 1024 * 1024)
+            .spawn(|| {
+                let err = test_eval_error("let f = fn(x) { f(x + 1) };\nf(1)");
+                (err.traceback(), err.kind, err.position, err.trace.len())
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+
+        assert_eq!(kind, RECURSION_ERROR);
+        assert_eq!(
+            position,
+            Some(Position {
+                line: 1,
+                column: 18
+            })
+        );
+        assert_eq!(depth, MAX_CALL_DEPTH);
+        assert_eq!(
+            traceback,
+            format!(
+                "Traceback (most recent call last):\n  \
+                 in f at line 2, column 2\n  \
+                 in f at line 1, column 18\n  \
+                 in f at line 1, column 18\n  \
+                 in f at line 1, column 18\n  \
+                 [previous frame repeated {} more times]\n\
+                 RecursionError at line 1, column 18: maximum call depth of 1000 exceeded",
+                MAX_CALL_DEPTH - 4
+            )
+        );
+
+        // Recursing through a default value hits the same limit
+        let (kind, depth, caught) = std::thread::Builder::new()
+            .stack_size(256 * 1024 * 1024)
+            .spawn(|| {
+                let err = test_eval_error("fn f(a = f()) { 1 } f()");
+                let caught =
+                    test_eval_program("fn f(a = f()) { 1 } try { f() } catch (e) { e[\"kind\"] }");
+                let caught = caught == Object::String(String::from("RecursionError"));
+                (err.kind, err.trace.len(), caught)
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+        assert_eq!(kind, RECURSION_ERROR);
+        assert_eq!(depth, MAX_CALL_DEPTH);
+        assert!(caught);
+    }
+
+    #[test]
+    fn test_eval_null() {
         let test_cases = vec![
             TestCase {
-                test_str: "let x = \"cartman\"; len(x)",
-                val: Object::Integer(7),
+                test_str: "null",
+                val: Object::Null,
             },
             TestCase {
-                test_str: "len(\"cartman\");",
-                val: Object::Integer(7),
+                test_str: "let x = {\"a\": 1}; x[\"b\"] == null",
+                val: Object::Bool(true),
+            },
+            TestCase {
+                test_str: "null != 1",
+                val: Object::Bool(true),
+            },
+            TestCase {
+                test_str: "let f = fn(x) { x == null }; f(null)",
+                val: Object::Bool(true),
+            },
+            TestCase {
+                test_str: "let x = {\"a\": 1}; x[\"b\"] ?? 10",
+                val: Object::Integer(10),
+            },
+            TestCase {
+                test_str: "let x = {\"a\": 1}; x[\"a\"] ?? 10",
+                val: Object::Integer(1),
+            },
+            TestCase {
+                test_str: "null ?? null ?? \"last\"",
+                val: Object::String(String::from("last")),
+            },
+            TestCase {
+                test_str: "1 ?? missing(2)",
+                val: Object::Integer(1),
+            },
+            TestCase {
+                test_str: "let x = null; x ?? 2",
+                val: Object::Integer(2),
             },
         ];
 
@@ -603,23 +3640,60 @@ mod tests {
     }
 
     #[test]
-    fn test_eval_arrays() {
+    fn test_eval_safe_navigation() {
         let test_cases = vec![
             TestCase {
-                test_str: "let x = [1, 2, 3]; x[2]",
+                test_str: "let cfg = {\"db\": {\"host\": \"local\"}}; cfg?[\"db\"]?[\"host\"]",
+                val: Object::String(String::from("local")),
+            },
+            TestCase {
+                test_str:
+                    "let cfg = {\"db\": {\"host\": \"local\"}}; cfg[\"web\"]?[\"host\"]?[\"port\"]",
+                val: Object::Null,
+            },
+            TestCase {
+                test_str: "let cfg = {\"db\": {}}; cfg[\"web\"]?[\"port\"] ?? 8080",
+                val: Object::Integer(8080),
+            },
+            TestCase {
+                test_str: "null?[1]",
+                val: Object::Null,
+            },
+            TestCase {
+                test_str: "let x = [1, 2]; x?[1]",
+                val: Object::Integer(2),
+            },
+            TestCase {
+                test_str: "let h = {\"f\": fn(x) { x * 2 }}; h[\"f\"]?.(21)",
+                val: Object::Integer(42),
+            },
+            TestCase {
+                test_str: "let h = {}; h[\"f\"]?.(missing(1))",
+                val: Object::Null,
+            },
+            TestCase {
+                test_str: "struct P { x } let p = P { x: 3 }; p?.x",
                 val: Object::Integer(3),
             },
             TestCase {
-                test_str: "let x = [1, 2, [1, 3]]; x[2][0]",
-                val: Object::Integer(1),
+                test_str: "let p = null; p?.x",
+                val: Object::Null,
             },
             TestCase {
-                test_str: "let x = [1, \"abc\", 32, 43]; x[1]",
-                val: Object::String(String::from("abc")),
+                test_str: "struct P { next } let p = P { next: null }; p?.next?.next ?? 7",
+                val: Object::Integer(7),
+            },
+            TestCase {
+                test_str: "enum E { A } E?.A == null",
+                val: Object::Bool(false),
             },
         ];
 
         check_test_cases(test_cases);
+
+        let err = test_eval_error("struct P { x } let p = P { x: 1 };\np?.y");
+        assert_eq!(err.kind, ATTRIBUTE_ERROR);
+        assert_eq!(err.position, Some(Position { line: 2, column: 2 }));
     }
 
     #[test]
@@ -0,0 +1,473 @@
+use crate::enviroment::EnviromentVariables;
+use crate::object::{ArrayRef, HashKey, HashMapRef, Instance, Object, StructRef, TupleRef};
+use indexmap::IndexMap;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::{Rc, Weak};
+
+/// Heap objects allocated between two automatic collections, at the least
+const MIN_THRESHOLD: usize = 1000;
+
+type Env = Rc<RefCell<EnviromentVariables>>;
+
+enum Tracked {
+    Environment(Weak<RefCell<EnviromentVariables>>),
+    Array(Weak<RefCell<Vec<Object>>>),
+    Tuple(Weak<[Object]>),
+    HashMap(Weak<RefCell<IndexMap<HashKey, Object>>>),
+    Struct(Weak<Instance>),
+}
+
+enum Node {
+    Environment(Env),
+    Array(ArrayRef),
+    Tuple(TupleRef),
+    HashMap(HashMapRef),
+    Struct(StructRef),
+}
+
+impl Tracked {
+    fn is_live(&self) -> bool {
+        match self {
+            Tracked::Environment(env) => env.strong_count() > 0,
+            Tracked::Array(items) => items.strong_count() > 0,
+            Tracked::Tuple(items) => items.strong_count() > 0,
+            Tracked::HashMap(map) => map.strong_count() > 0,
+            Tracked::Struct(instance) => instance.strong_count() > 0,
+        }
+    }
+
+    fn upgrade(&self) -> Option<Node> {
+        match self {
+            Tracked::Environment(env) => env.upgrade().map(Node::Environment),
+            Tracked::Array(items) => items.upgrade().map(Node::Array),
+            Tracked::Tuple(items) => items.upgrade().map(Node::Tuple),
+            Tracked::HashMap(map) => map.upgrade().map(Node::HashMap),
+            Tracked::Struct(instance) => instance.upgrade().map(Node::Struct),
+        }
+    }
+}
+
+impl Node {
+    fn id(&self) -> *const () {
+        match self {
+            Node::Environment(env) => Rc::as_ptr(env) as *const (),
+            Node::Array(items) => Rc::as_ptr(items) as *const (),
+            Node::Tuple(items) => Rc::as_ptr(items) as *const (),
+            Node::HashMap(map) => Rc::as_ptr(map) as *const (),
+            Node::Struct(instance) => Rc::as_ptr(instance) as *const (),
+        }
+    }
+
+    fn strong_count(&self) -> usize {
+        match self {
+            Node::Environment(env) => Rc::strong_count(env),
+            Node::Array(items) => Rc::strong_count(items),
+            Node::Tuple(items) => Rc::strong_count(items),
+            Node::HashMap(map) => Rc::strong_count(map),
+            Node::Struct(instance) => Rc::strong_count(instance),
+        }
+    }
+
+    /// Returns `false` without calling `f` when the node is borrowed
+    fn for_each_reference(&self, f: &mut dyn FnMut(*const ())) -> bool {
+        match self {
+            Node::Environment(env) => match env.try_borrow() {
+                Ok(env) => env.for_each_reference(f),
+                Err(_) => return false,
+            },
+            Node::Array(items) => match items.try_borrow() {
+                Ok(items) => items.iter().for_each(|item| item.for_each_reference(f)),
+                Err(_) => return false,
+            },
+            Node::Tuple(items) => items.iter().for_each(|item| item.for_each_reference(f)),
+            Node::HashMap(map) => match map.try_borrow() {
+                Ok(map) => map.values().for_each(|value| value.for_each_reference(f)),
+                Err(_) => return false,
+            },
+            Node::Struct(instance) => match instance.fields.try_borrow() {
+                Ok(fields) => {
+                    f(Rc::as_ptr(&instance.env) as *const ());
+                    fields.iter().for_each(|field| field.for_each_reference(f))
+                }
+                Err(_) => return false,
+            },
+        }
+        true
+    }
+
+    fn clear(&self) {
+        match self {
+            Node::Environment(env) => env.borrow_mut().clear(),
+            Node::Array(items) => items.borrow_mut().clear(),
+            // Tuples are immutable, but every cycle through one also passes
+            // through a scope, array or hashmap whose clearing frees it
+            Node::Tuple(_) => {}
+            Node::HashMap(map) => map.borrow_mut().clear(),
+            Node::Struct(instance) => instance.fields.borrow_mut().clear(),
+        }
+    }
+}
+
+///
+///  Every environment, array, tuple, hashmap and struct instance allocated by the interpreter is
+///  registered here so reference cycles between them, like a closure stored
+///  in the scope it captured or an array holding itself, which `Rc` alone
+///  can never free, can be found and broken
+///
+struct Heap {
+    objects: Vec<Tracked>,
+    threshold: usize,
+    collections: usize,
+    freed: usize,
+}
+
+thread_local! {
+    static HEAP: RefCell<Heap> = const {
+        RefCell::new(Heap {
+            objects: Vec::new(),
+            threshold: MIN_THRESHOLD,
+            collections: 0,
+            freed: 0,
+        })
+    };
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GcStats {
+    pub environments: usize,
+    pub arrays: usize,
+    pub tuples: usize,
+    pub hashmaps: usize,
+    pub structs: usize,
+    pub collections: usize,
+    pub freed: usize,
+    pub threshold: usize,
+}
+
+fn track(object: Tracked) {
+    let registered = HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.objects.push(object);
+        heap.objects.len()
+    });
+
+    if registered >= HEAP.with(|heap| heap.borrow().threshold) {
+        collect();
+    }
+}
+
+pub fn allocate(env: EnviromentVariables) -> Env {
+    let env = Rc::new(RefCell::new(env));
+    track(Tracked::Environment(Rc::downgrade(&env)));
+    env
+}
+
+pub fn allocate_array(items: Vec<Object>) -> ArrayRef {
+    let items = Rc::new(RefCell::new(items));
+    track(Tracked::Array(Rc::downgrade(&items)));
+    items
+}
+
+pub fn allocate_tuple(items: Vec<Object>) -> TupleRef {
+    let items: TupleRef = Rc::from(items);
+    track(Tracked::Tuple(Rc::downgrade(&items)));
+    items
+}
+
+pub fn allocate_hashmap(map: IndexMap<HashKey, Object>) -> HashMapRef {
+    let map = Rc::new(RefCell::new(map));
+    track(Tracked::HashMap(Rc::downgrade(&map)));
+    map
+}
+
+pub fn allocate_struct(instance: Instance) -> StructRef {
+    let instance = Rc::new(instance);
+    track(Tracked::Struct(Rc::downgrade(&instance)));
+    instance
+}
+
+pub fn stats() -> GcStats {
+    HEAP.with(|heap| {
+        let heap = heap.borrow();
+        let mut stats = GcStats {
+            environments: 0,
+            arrays: 0,
+            tuples: 0,
+            hashmaps: 0,
+            structs: 0,
+            collections: heap.collections,
+            freed: heap.freed,
+            threshold: heap.threshold,
+        };
+        for object in heap.objects.iter().filter(|object| object.is_live()) {
+            match object {
+                Tracked::Environment(_) => stats.environments += 1,
+                Tracked::Array(_) => stats.arrays += 1,
+                Tracked::Tuple(_) => stats.tuples += 1,
+                Tracked::HashMap(_) => stats.hashmaps += 1,
+                Tracked::Struct(_) => stats.structs += 1,
+            }
+        }
+        stats
+    })
+}
+
+///
+///  Frees heap objects that are only reachable from other heap objects,
+///  returning how many were freed.
+///
+///  This is a trial deletion collector: the references every object holds
+///  to the others (a scope's outer scope and variables, an array's or a
+///  tuple's items, a hashmap's values, an instance's fields) are subtracted from their strong counts, and whatever
+///  still has references left is held by the running interpreter and is a
+///  root. Everything not reachable from a root is garbage, and clearing it
+///  breaks its cycles
+///
+pub fn collect() -> usize {
+    let nodes: Vec<Node> = HEAP.with(|heap| {
+        heap.borrow()
+            .objects
+            .iter()
+            .filter_map(Tracked::upgrade)
+            .collect()
+    });
+
+    let index: HashMap<*const (), usize> = nodes
+        .iter()
+        .enumerate()
+        .map(|(idx, node)| (node.id(), idx))
+        .collect();
+
+    // An object that is borrowed right now is in use, so it is kept as a
+    // root. Its references can't be read, so they are left out of the
+    // internal counts, which keeps everything it refers to alive as well
+    let references: Vec<Option<Vec<usize>>> = nodes
+        .iter()
+        .map(|node| {
+            let mut refs = vec![];
+            let scanned = node.for_each_reference(&mut |referenced| {
+                if let Some(idx) = index.get(&referenced) {
+                    refs.push(*idx);
+                }
+            });
+            scanned.then_some(refs)
+        })
+        .collect();
+
+    let mut internal = vec![0; nodes.len()];
+    for refs in references.iter().flatten() {
+        for idx in refs {
+            internal[*idx] += 1;
+        }
+    }
+
+    // `nodes` holds one strong reference to each of them itself
+    let mut reachable: Vec<bool> = nodes
+        .iter()
+        .zip(&internal)
+        .zip(&references)
+        .map(|((node, internal), refs)| refs.is_none() || node.strong_count() - 1 > *internal)
+        .collect();
+    let mut pending: Vec<usize> = (0..nodes.len()).filter(|idx| reachable[*idx]).collect();
+    while let Some(idx) = pending.pop() {
+        for referenced in references[idx].iter().flatten() {
+            if !reachable[*referenced] {
+                reachable[*referenced] = true;
+                pending.push(*referenced);
+            }
+        }
+    }
+
+    let mut freed = 0;
+    for (node, reachable) in nodes.iter().zip(&reachable) {
+        if !reachable {
+            node.clear();
+            freed += 1;
+        }
+    }
+    let survivors = nodes.len() - freed;
+    drop(nodes);
+
+    HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.objects.retain(Tracked::is_live);
+        heap.threshold = MIN_THRESHOLD.max(survivors * 2);
+        heap.collections += 1;
+        heap.freed += freed;
+    });
+    freed
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::enviroment::EnviromentVariables;
+    use crate::evaluator::eval_program;
+    use crate::gc::*;
+    use crate::lexer::Lexer;
+    use crate::object::Object;
+    use crate::parser::Parser;
+    use crate::resolver::resolve_program;
+
+    fn run(input: &str, env: &mut Env) -> Object {
+        let lexer = Lexer::new(input).unwrap();
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program().unwrap();
+        resolve_program(&program).unwrap();
+        eval_program(program.as_ref(), env).unwrap()
+    }
+
+    #[test]
+    fn test_collects_closure_cycles() {
+        let mut env = allocate(EnviromentVariables::new());
+        run(
+            "fn make() { fn helper() { 1 }; helper } make(); make();",
+            &mut env,
+        );
+
+        // Each call scope holds `helper`, which captured that same scope
+        assert_eq!(stats().environments, 3);
+        assert_eq!(collect(), 2);
+        assert_eq!(stats().environments, 1);
+
+        assert_eq!(run("make()()", &mut env), Object::Integer(1));
+    }
+
+    #[test]
+    fn test_keeps_reachable_environments() {
+        let mut env = allocate(EnviromentVariables::new());
+        run(
+            "fn make() { let mut n = 0; fn next() { n = n + 1 }; next }\
+             let counter = make(); counter();",
+            &mut env,
+        );
+
+        assert_eq!(collect(), 0);
+        assert_eq!(run("counter()", &mut env), Object::Integer(2));
+    }
+
+    #[test]
+    fn test_collects_dropped_global_scope() {
+        let mut env = allocate(EnviromentVariables::new());
+        run("let f = fn() { 1 }; let fs = [f, {\"g\": f}];", &mut env);
+        let scope = Rc::downgrade(&env);
+        drop(env);
+
+        // The scope, the array and the hashmap
+        assert!(scope.upgrade().is_some());
+        assert_eq!(collect(), 3);
+        assert!(scope.upgrade().is_none());
+        assert_eq!(stats().environments, 0);
+        assert_eq!(stats().arrays, 0);
+        assert_eq!(stats().hashmaps, 0);
+    }
+
+    #[test]
+    fn test_collects_container_cycles() {
+        let mut env = allocate(EnviromentVariables::new());
+        run(
+            "fn make() { let a = [1]; let m = {\"a\": a}; push(a, m); push(a, a); len(a) }\
+             make();",
+            &mut env,
+        );
+
+        assert_eq!(stats().arrays, 1);
+        assert_eq!(stats().hashmaps, 1);
+        assert_eq!(collect(), 2);
+        assert_eq!(stats().arrays, 0);
+        assert_eq!(stats().hashmaps, 0);
+
+        run(
+            "struct Node { next } fn link() { let n = Node { next: null }; n.next = n; null }\
+             link();",
+            &mut env,
+        );
+        assert_eq!(stats().structs, 1);
+        assert_eq!(collect(), 1);
+        assert_eq!(stats().structs, 0);
+
+        run("let kept = [0]; kept[0] = kept;", &mut env);
+        assert_eq!(collect(), 0);
+        assert_eq!(stats().arrays, 1);
+    }
+
+    #[test]
+    fn test_collects_tuple_cycles() {
+        let mut env = allocate(EnviromentVariables::new());
+        run(
+            "fn make() { let a = []; push(a, (a, 1)); len(a) } make();\
+             let shared = ([0],); let x = shared; let y = shared;",
+            &mut env,
+        );
+
+        // The array and the tuple it holds, which holds the array again
+        assert_eq!(collect(), 2);
+        assert_eq!(stats().tuples, 1);
+        assert_eq!(stats().arrays, 1);
+        assert_eq!(run("x[0][0]", &mut env), Object::Integer(0));
+    }
+
+    #[test]
+    fn test_collects_variant_cycles() {
+        let mut env = allocate(EnviromentVariables::new());
+        run(
+            "enum List { Cons(head, tail), Nil }\
+             fn make() { let a = []; push(a, List.Cons(1, a)); len(a) } make();",
+            &mut env,
+        );
+
+        // The array and the values of the variant it holds
+        assert_eq!(collect(), 2);
+        assert_eq!(stats().tuples, 0);
+        assert_eq!(stats().arrays, 0);
+    }
+
+    #[test]
+    fn test_collects_composition_cycles() {
+        let mut env = allocate(EnviromentVariables::new());
+        run(
+            "fn make() { let a = []; let f = fn() { a }; push(a, f >> len); len(a) } make();",
+            &mut env,
+        );
+
+        // The call's environment, which the composed function closes over,
+        // and the array holding the composition
+        assert_eq!(collect(), 2);
+        assert_eq!(stats().arrays, 0);
+    }
+
+    #[test]
+    fn test_automatic_collection() {
+        let mut env = allocate(EnviromentVariables::new());
+        run("fn make() { fn helper() { 1 }; helper }", &mut env);
+        for _ in 0..5 * MIN_THRESHOLD {
+            run("make();", &mut env);
+        }
+
+        let stats = stats();
+        assert!(stats.collections >= 4);
+        assert!(stats.environments <= MIN_THRESHOLD);
+        assert_eq!(stats.freed + stats.environments, 5 * MIN_THRESHOLD + 1);
+    }
+
+    #[test]
+    fn test_gc_builtins() {
+        let mut env = allocate(EnviromentVariables::new());
+        let freed = run(
+            "fn make() { fn helper() { 1 }; helper }\
+             fn churn() { make(); make(); make(); null }\
+             churn(); gc()",
+            &mut env,
+        );
+        assert_eq!(freed, Object::Integer(3));
+
+        let stats = run(
+            "let s = gc_stats(); [s[\"environments\"], s[\"freed\"]]",
+            &mut env,
+        );
+        assert_eq!(
+            stats,
+            Object::array(vec![Object::Integer(1), Object::Integer(3)])
+        );
+    }
+}
@@ -1,54 +1,486 @@
-use crate::object::Object;
+use crate::evaluator::{
+    apply_callback, RuntimeError, ARGUMENT_ERROR, ERROR, OVERFLOW_ERROR, TYPE_ERROR,
+};
+use crate::gc;
+use crate::lexer::Position;
+use crate::object::{range_len, HashKey, HashMapRef, Instance, Object};
+use indexmap::IndexMap;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+pub const BUILTINS: [&str; 17] = [
+    "len",
+    "print",
+    "error",
+    "is_error",
+    "gc",
+    "gc_stats",
+    "push",
+    "copy",
+    "deep_copy",
+    "keys",
+    "values",
+    "map",
+    "sum",
+    "upper",
+    "lower",
+    "trim",
+    "split",
+];
+
+///
+///  The methods of the builtin types by type name. Each is the builtin of the
+///  same name, called with the value the method is called on first
+///
+const METHODS: [(&str, &str); 26] = [
+    ("array", "len"),
+    ("array", "push"),
+    ("array", "map"),
+    ("array", "sum"),
+    ("array", "copy"),
+    ("array", "deep_copy"),
+    ("tuple", "len"),
+    ("tuple", "map"),
+    ("tuple", "sum"),
+    ("range", "len"),
+    ("range", "map"),
+    ("range", "sum"),
+    ("string", "len"),
+    ("string", "map"),
+    ("string", "upper"),
+    ("string", "lower"),
+    ("string", "trim"),
+    ("string", "split"),
+    ("hashmap", "len"),
+    ("hashmap", "keys"),
+    ("hashmap", "values"),
+    ("hashmap", "copy"),
+    ("hashmap", "deep_copy"),
+    ("error", "len"),
+    ("error", "keys"),
+    ("error", "values"),
+];
 
 pub fn get_builtin(identifier: &str) -> Option<Object> {
-    match identifier {
-        "len" => Some(Object::BuiltInFunction(String::from("len"))),
-        "print" => Some(Object::BuiltInFunction(String::from("print"))),
-        _ => None,
-    }
+    BUILTINS
+        .iter()
+        .find(|name| **name == identifier)
+        .map(|name| Object::BuiltInFunction(name.to_string()))
 }
 
-fn process_len(args: &[Object]) -> Object {
-    if args.len() != 1 {
-        panic!(
-            "Expected one argument for len found {} arguments",
-            args.len()
-        )
+pub fn get_method(receiver: &Object, method: &str) -> Option<Object> {
+    METHODS
+        .iter()
+        .find(|(type_name, name)| *type_name == receiver.type_name() && *name == method)
+        .map(|(_, name)| Object::BuiltInFunction(name.to_string()))
+}
+
+fn check_arg_count(name: &str, args: &[Object], expected: usize) -> Result<(), RuntimeError> {
+    if args.len() != expected {
+        return Err(RuntimeError::new(
+            ARGUMENT_ERROR,
+            format!(
+                "Expected {} argument(s) for {} found {} arguments",
+                expected,
+                name,
+                args.len()
+            ),
+        ));
     }
+    Ok(())
+}
+
+fn process_len(args: &[Object]) -> Result<Object, RuntimeError> {
+    check_arg_count("len", args, 1)?;
 
     let argument = &args[0];
 
     match argument {
-        Object::String(s) => Object::Integer(s.len() as i64),
-        Object::Array(o) => Object::Integer(o.len() as i64),
-        _ => panic!("Expected string or array argument"),
+        Object::String(s) => Ok(Object::Integer(s.chars().count() as i64)),
+        Object::Array(o) => Ok(Object::Integer(o.borrow().len() as i64)),
+        Object::Tuple(items) => Ok(Object::Integer(items.len() as i64)),
+        Object::HashMap(map) => Ok(Object::Integer(map.borrow().len() as i64)),
+        Object::Err(err) => Ok(Object::Integer(err.fields().len() as i64)),
+        Object::Range(start, end) => i64::try_from(range_len(*start, *end))
+            .map(Object::Integer)
+            .map_err(|_| {
+                RuntimeError::new(
+                    OVERFLOW_ERROR,
+                    format!("length of {} does not fit in an integer", argument),
+                )
+            }),
+        _ => Err(RuntimeError::new(
+            TYPE_ERROR,
+            format!(
+                "Expected string, array, tuple, range or hashmap argument for len, found {}",
+                argument.type_name()
+            ),
+        )),
     }
 }
 
-fn procces_print(args: &[Object]) -> Object {
-    if args.len() != 1 {
-        panic!(
-            "Expected one argument for print found {} arguments",
-            args.len()
-        )
-    }
+fn procces_print(args: &[Object]) -> Result<Object, RuntimeError> {
+    check_arg_count("print", args, 1)?;
 
     let argument = &args[0];
 
     match argument {
-        Object::String(s) => Object::String(s.to_string()),
-        Object::Array(a) => Object::Array(a.to_vec()),
-        Object::HashMap(h) => Object::HashMap(h.clone()),
-        Object::Integer(i) => Object::Integer(*i),
-        _ => panic!("Expected a string | array | hashmap | integer  argument")
+        Object::String(_) | Object::Array(_) | Object::HashMap(_) | Object::Integer(_) => {
+            Ok(argument.clone())
+        }
+        _ => Err(RuntimeError::new(
+            TYPE_ERROR,
+            format!(
+                "Expected a string | array | hashmap | integer argument for print, found {}",
+                argument.type_name()
+            ),
+        )),
+    }
+}
+
+fn process_error(args: &[Object]) -> Result<Object, RuntimeError> {
+    let (message, kind) = match args {
+        [Object::String(message)] => (message, ERROR),
+        [Object::String(message), Object::String(kind)] => (message, kind.as_str()),
+        _ => {
+            return Err(RuntimeError::new(
+                ARGUMENT_ERROR,
+                String::from(
+                    "Expected error(message) or error(message, kind) with string arguments",
+                ),
+            ))
+        }
+    };
+
+    Ok(Object::Err(RuntimeError::new(kind, message.to_string())))
+}
+
+fn process_is_error(args: &[Object]) -> Result<Object, RuntimeError> {
+    check_arg_count("is_error", args, 1)?;
+    Ok(Object::Bool(matches!(args[0], Object::Err(_))))
+}
+
+fn process_gc(args: &[Object]) -> Result<Object, RuntimeError> {
+    check_arg_count("gc", args, 0)?;
+    Ok(Object::Integer(gc::collect() as i64))
+}
+
+fn process_gc_stats(args: &[Object]) -> Result<Object, RuntimeError> {
+    check_arg_count("gc_stats", args, 0)?;
+
+    let stats = gc::stats();
+    let entries = [
+        ("environments", stats.environments),
+        ("arrays", stats.arrays),
+        ("tuples", stats.tuples),
+        ("hashmaps", stats.hashmaps),
+        ("structs", stats.structs),
+        ("collections", stats.collections),
+        ("freed", stats.freed),
+        ("threshold", stats.threshold),
+    ];
+    Ok(Object::hashmap(IndexMap::from(entries.map(
+        |(name, value)| {
+            (
+                HashKey::String(name.to_string()),
+                Object::Integer(value as i64),
+            )
+        },
+    ))))
+}
+
+fn process_push(args: &[Object]) -> Result<Object, RuntimeError> {
+    check_arg_count("push", args, 2)?;
+
+    match &args[0] {
+        Object::Array(items) => {
+            items.borrow_mut().push(args[1].clone());
+            Ok(args[0].clone())
+        }
+        argument => Err(RuntimeError::new(
+            TYPE_ERROR,
+            format!(
+                "Expected an array argument for push, found {}",
+                argument.type_name()
+            ),
+        )),
+    }
+}
+
+fn process_copy(args: &[Object]) -> Result<Object, RuntimeError> {
+    check_arg_count("copy", args, 1)?;
+
+    match &args[0] {
+        Object::Array(items) => Ok(Object::array(items.borrow().clone())),
+        Object::HashMap(map) => Ok(Object::hashmap(map.borrow().clone())),
+        Object::Struct(instance) => Ok(Object::instance(
+            instance.def.clone(),
+            instance.env.clone(),
+            instance.fields.borrow().clone(),
+        )),
+        argument => Ok(argument.clone()),
+    }
+}
+
+///
+///  Copies nested arrays, hashmaps and struct instances too. `copies` maps each container
+///  already copied to its copy, so shared and cyclic containers stay shared
+///  and cyclic in the copy
+///
+fn deep_copy(value: &Object, copies: &mut HashMap<*const (), Object>) -> Object {
+    match value {
+        Object::Array(items) => {
+            let id = Rc::as_ptr(items) as *const ();
+            if let Some(copy) = copies.get(&id) {
+                return copy.clone();
+            }
+
+            let copy = gc::allocate_array(vec![]);
+            copies.insert(id, Object::Array(copy.clone()));
+            let copied = items
+                .borrow()
+                .iter()
+                .map(|item| deep_copy(item, copies))
+                .collect();
+            *copy.borrow_mut() = copied;
+            Object::Array(copy)
+        }
+        Object::HashMap(map) => {
+            let id = Rc::as_ptr(map) as *const ();
+            if let Some(copy) = copies.get(&id) {
+                return copy.clone();
+            }
+
+            let copy = gc::allocate_hashmap(IndexMap::new());
+            copies.insert(id, Object::HashMap(copy.clone()));
+            let copied = map
+                .borrow()
+                .iter()
+                .map(|(key, value)| (key.clone(), deep_copy(value, copies)))
+                .collect();
+            *copy.borrow_mut() = copied;
+            Object::HashMap(copy)
+        }
+        Object::Struct(instance) => {
+            let id = Rc::as_ptr(instance) as *const ();
+            if let Some(copy) = copies.get(&id) {
+                return copy.clone();
+            }
+
+            let copy = gc::allocate_struct(Instance {
+                def: instance.def.clone(),
+                env: instance.env.clone(),
+                fields: RefCell::new(vec![]),
+            });
+            copies.insert(id, Object::Struct(copy.clone()));
+            let copied = instance
+                .fields
+                .borrow()
+                .iter()
+                .map(|field| deep_copy(field, copies))
+                .collect();
+            *copy.fields.borrow_mut() = copied;
+            Object::Struct(copy)
+        }
+        Object::Tuple(items) => {
+            Object::tuple(items.iter().map(|item| deep_copy(item, copies)).collect())
+        }
+        Object::Variant(def, variant, values) => Object::variant(
+            def.clone(),
+            *variant,
+            values
+                .iter()
+                .map(|value| deep_copy(value, copies))
+                .collect(),
+        ),
+        _ => value.clone(),
+    }
+}
+
+fn process_deep_copy(args: &[Object]) -> Result<Object, RuntimeError> {
+    check_arg_count("deep_copy", args, 1)?;
+    Ok(deep_copy(&args[0], &mut HashMap::new()))
+}
+
+///
+///  The hashmap argument of `name`. A caught error reads as a hashmap of its
+///  fields
+///
+fn hashmap_argument(name: &str, args: &[Object]) -> Result<HashMapRef, RuntimeError> {
+    check_arg_count(name, args, 1)?;
+
+    match &args[0] {
+        Object::HashMap(map) => Ok(map.clone()),
+        Object::Err(err) => Ok(gc::allocate_hashmap(err.fields())),
+        argument => Err(RuntimeError::new(
+            TYPE_ERROR,
+            format!(
+                "Expected a hashmap argument for {}, found {}",
+                name,
+                argument.type_name()
+            ),
+        )),
+    }
+}
+
+/// The string at `args[index]`, or a TypeError naming the builtin `name`
+fn string_argument<'a>(
+    name: &str,
+    args: &'a [Object],
+    index: usize,
+) -> Result<&'a str, RuntimeError> {
+    match &args[index] {
+        Object::String(s) => Ok(s),
+        argument => Err(RuntimeError::new(
+            TYPE_ERROR,
+            format!(
+                "Expected a string argument for {}, found {}",
+                name,
+                argument.type_name()
+            ),
+        )),
+    }
+}
+
+fn process_upper(args: &[Object]) -> Result<Object, RuntimeError> {
+    check_arg_count("upper", args, 1)?;
+    Ok(Object::String(
+        string_argument("upper", args, 0)?.to_uppercase(),
+    ))
+}
+
+fn process_lower(args: &[Object]) -> Result<Object, RuntimeError> {
+    check_arg_count("lower", args, 1)?;
+    Ok(Object::String(
+        string_argument("lower", args, 0)?.to_lowercase(),
+    ))
+}
+
+fn process_trim(args: &[Object]) -> Result<Object, RuntimeError> {
+    check_arg_count("trim", args, 1)?;
+    Ok(Object::String(
+        string_argument("trim", args, 0)?.trim().to_string(),
+    ))
+}
+
+/// Splits a string into an array of the parts between each separator
+fn process_split(args: &[Object]) -> Result<Object, RuntimeError> {
+    check_arg_count("split", args, 2)?;
+
+    let s = string_argument("split", args, 0)?;
+    let separator = string_argument("split", args, 1)?;
+    if separator.is_empty() {
+        return Err(RuntimeError::new(
+            ARGUMENT_ERROR,
+            "Expected a non-empty separator for split".to_string(),
+        ));
+    }
+    let parts = s
+        .split(separator)
+        .map(|part| Object::String(part.to_string()))
+        .collect();
+    Ok(Object::array(parts))
+}
+
+/// The keys of a hashmap, in the order they were inserted
+fn process_keys(args: &[Object]) -> Result<Object, RuntimeError> {
+    let map = hashmap_argument("keys", args)?;
+    let keys = map.borrow().keys().map(HashKey::to_object).collect();
+    Ok(Object::array(keys))
+}
+
+/// The values of a hashmap, in the order their keys were inserted
+fn process_values(args: &[Object]) -> Result<Object, RuntimeError> {
+    let map = hashmap_argument("values", args)?;
+    let values = map.borrow().values().cloned().collect();
+    Ok(Object::array(values))
+}
+
+///
+///  Calls `args[1]` with every item of `args[0]`, collecting the results into
+///  an array
+///
+fn process_map(args: &[Object], position: Position) -> Result<Object, RuntimeError> {
+    check_arg_count("map", args, 2)?;
+
+    let items = args[0].items().ok_or_else(|| {
+        RuntimeError::new(
+            TYPE_ERROR,
+            format!(
+                "Expected an iterable argument for map, found {}",
+                args[0].type_name()
+            ),
+        )
+    })?;
+    let mapped = items
+        .map(|item| apply_callback(args[1].clone(), &[item], position))
+        .collect::<Result<Vec<Object>, RuntimeError>>()?;
+    Ok(Object::array(mapped))
+}
+
+///
+///  Adds up the integers of an array, tuple or range
+///
+fn process_sum(args: &[Object]) -> Result<Object, RuntimeError> {
+    check_arg_count("sum", args, 1)?;
+
+    let items = match &args[0] {
+        Object::String(_) => None,
+        argument => argument.items(),
+    }
+    .ok_or_else(|| {
+        RuntimeError::new(
+            TYPE_ERROR,
+            format!(
+                "Expected an array, tuple or range argument for sum, found {}",
+                args[0].type_name()
+            ),
+        )
+    })?;
+
+    let mut total: i64 = 0;
+    for item in items {
+        let Object::Integer(i) = item else {
+            return Err(RuntimeError::new(
+                TYPE_ERROR,
+                format!("Expected integers to sum, found {}", item.type_name()),
+            ));
+        };
+        total = total.checked_add(i).ok_or_else(|| {
+            RuntimeError::new(
+                OVERFLOW_ERROR,
+                format!("integer overflow in sum of {}", args[0]),
+            )
+        })?;
     }
+    Ok(Object::Integer(total))
 }
 
-pub fn eval_builtin(func_obj: &Object, args: &[Object]) -> Object {
+pub fn eval_builtin(
+    func_obj: &Object,
+    args: &[Object],
+    position: Position,
+) -> Result<Object, RuntimeError> {
     match func_obj {
         Object::BuiltInFunction(func_name) => match func_name.as_str() {
             "len" => process_len(args),
             "print" => procces_print(args),
+            "error" => process_error(args),
+            "is_error" => process_is_error(args),
+            "gc" => process_gc(args),
+            "gc_stats" => process_gc_stats(args),
+            "push" => process_push(args),
+            "copy" => process_copy(args),
+            "deep_copy" => process_deep_copy(args),
+            "keys" => process_keys(args),
+            "values" => process_values(args),
+            "map" => process_map(args, position),
+            "sum" => process_sum(args),
+            "upper" => process_upper(args),
+            "lower" => process_lower(args),
+            "trim" => process_trim(args),
+            "split" => process_split(args),
             _ => panic!("Invalid inbuilt function"),
         },
         _ => panic!("Expected a function object but found {}", func_obj),
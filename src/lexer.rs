@@ -1,6 +1,7 @@
 use std::fmt;
 use std::fmt::Debug;
 use std::iter::from_fn;
+use std::num::IntErrorKind;
 use std::str::Chars;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -17,16 +18,38 @@ pub enum Token {
     Asterisk,
     Lt,
     Gt,
+    Ampersand,
+    Pipe,
+    Pipeline,
+    Caret,
+    Tilde,
+    ShiftLeft,
+    ShiftRight,
     Eq,
     NotEq,
     Comma,
     Colon,
     Let,
+    Mut,
+    Const,
     True,
     False,
     If,
     Else,
     Ret,
+    Null,
+    Throw,
+    Try,
+    Catch,
+    Finally,
+    NullCoalesce,
+    Question,
+    QuestionDot,
+    QuestionBracket,
+    Ellipsis,
+    Dot,
+    DotDot,
+    DotDotEq,
     Semicolon,
     LParen,
     RParen,
@@ -35,6 +58,10 @@ pub enum Token {
     LBracket,
     RBracket,
     Func,
+    Struct,
+    Enum,
+    Match,
+    FatArrow,
 }
 
 fn from_string(token: &Token) -> String {
@@ -54,33 +81,109 @@ fn from_string(token: &Token) -> String {
         Token::Slash => String::from("/"),
         Token::Asterisk => String::from("*"),
         Token::Lt => String::from("<"),
+        Token::Ampersand => String::from("&"),
+        Token::Pipe => String::from("|"),
+        Token::Pipeline => String::from("|>"),
+        Token::Caret => String::from("^"),
+        Token::Tilde => String::from("~"),
+        Token::ShiftLeft => String::from("<<"),
+        Token::ShiftRight => String::from(">>"),
         Token::Minus => String::from("-"),
         Token::Colon => String::from(':'),
         Token::LParen => String::from("("),
         Token::RParen => String::from(")"),
         Token::Func => String::from("fn"),
+        Token::Struct => String::from("struct"),
+        Token::Enum => String::from("enum"),
+        Token::Match => String::from("match"),
+        Token::FatArrow => String::from("=>"),
         Token::Let => String::from("let"),
+        Token::Mut => String::from("mut"),
+        Token::Const => String::from("const"),
         Token::True => String::from("true"),
         Token::False => String::from("false"),
         Token::If => String::from("if"),
         Token::Else => String::from("else"),
         Token::Ret => String::from("return"),
+        Token::Null => String::from("null"),
+        Token::Throw => String::from("throw"),
+        Token::Try => String::from("try"),
+        Token::Catch => String::from("catch"),
+        Token::Finally => String::from("finally"),
+        Token::NullCoalesce => String::from("??"),
+        Token::Question => String::from("?"),
+        Token::QuestionDot => String::from("?."),
+        Token::Ellipsis => String::from("..."),
+        Token::Dot => String::from("."),
+        Token::DotDot => String::from(".."),
+        Token::DotDotEq => String::from("..="),
+        Token::QuestionBracket => String::from("?["),
         Token::LBrace => String::from("{"),
         Token::RBrace => String::from("}"),
         Token::LBracket => String::from("["),
         Token::RBracket => String::from("]"),
     }
 }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum LexError {
+    IllegalCharacter(char, Position),
+    InvalidInteger(String, Position),
+    IntegerOutOfRange(String, Position),
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LexError::IllegalCharacter(c, pos) => {
+                write!(f, "Illegal character '{}' at {}", c, pos)
+            }
+            LexError::InvalidInteger(literal, pos) => {
+                write!(f, "Invalid integer literal '{}' at {}", literal, pos)
+            }
+            LexError::IntegerOutOfRange(literal, pos) => {
+                write!(f, "Integer literal '{}' out of range at {}", literal, pos)
+            }
+        }
+    }
+}
+
 struct Tokenizer<'a> {
     chars: Chars<'a>,
     current: Option<char>,
+    line: usize,
+    column: usize,
 }
 
 impl<'a> Tokenizer<'a> {
     pub fn new(input: &'a str) -> Self {
         let mut chars = input.chars();
         let current = chars.next();
-        Self { chars, current }
+        Self {
+            chars,
+            current,
+            line: 1,
+            column: 1,
+        }
+    }
+
+    fn position(&self) -> Position {
+        Position {
+            line: self.line,
+            column: self.column,
+        }
     }
 
     fn consume(&mut self, t: Token) -> Option<Token> {
@@ -94,16 +197,22 @@ impl<'a> Tokenizer<'a> {
         compound: Token,
         single: Token,
     ) -> Option<Token> {
-        match self.advance_char()? {
-            c if c == expected => self.consume(compound),
+        match self.advance_char() {
+            Some(c) if c == expected => self.consume(compound),
             _ => Some(single),
         }
     }
 
-    fn next_token(&mut self) -> Option<Token> {
+    fn next_token(&mut self) -> Result<Option<(Token, Position)>, LexError> {
         self.skip_whitespace();
 
-        match self.current? {
+        let position = self.position();
+        let current = match self.current {
+            Some(c) => c,
+            None => return Ok(None),
+        };
+
+        let token = match current {
             '(' => self.consume(Token::LParen),
             ')' => self.consume(Token::RParen),
             '[' => self.consume(Token::LBracket),
@@ -114,50 +223,101 @@ impl<'a> Tokenizer<'a> {
             '-' => self.consume(Token::Minus),
             '/' => self.consume(Token::Slash),
             '*' => self.consume(Token::Asterisk),
-            '<' => self.consume(Token::Lt),
-            '>' => self.consume(Token::Gt),
+            '&' => self.consume(Token::Ampersand),
+            '|' => self.match_compound_token('>', Token::Pipeline, Token::Pipe),
+            '^' => self.consume(Token::Caret),
+            '~' => self.consume(Token::Tilde),
+            '?' => match self.advance_char() {
+                Some('?') => self.consume(Token::NullCoalesce),
+                Some('.') => self.consume(Token::QuestionDot),
+                Some('[') => self.consume(Token::QuestionBracket),
+                _ => Some(Token::Question),
+            },
+            '.' => match self.advance_char() {
+                Some('.') => match self.advance_char() {
+                    Some('.') => self.consume(Token::Ellipsis),
+                    Some('=') => self.consume(Token::DotDotEq),
+                    _ => Some(Token::DotDot),
+                },
+                _ => Some(Token::Dot),
+            },
             ',' => self.consume(Token::Comma),
             ':' => self.consume(Token::Colon),
             ';' => self.consume(Token::Semicolon),
 
-            '=' => self.match_compound_token('=', Token::Eq, Token::Assign),
+            '=' => match self.advance_char() {
+                Some('=') => self.consume(Token::Eq),
+                Some('>') => self.consume(Token::FatArrow),
+                _ => Some(Token::Assign),
+            },
             '!' => self.match_compound_token('=', Token::NotEq, Token::Bang),
+            '<' => self.match_compound_token('<', Token::ShiftLeft, Token::Lt),
+            '>' => self.match_compound_token('>', Token::ShiftRight, Token::Gt),
             '"' => Some(Token::String(self.is_string())),
 
-            a if a.is_alphabetic() => {
+            a if a.is_alphabetic() || a == '_' => {
                 let result = self.is_keyword();
                 match result.as_str() {
                     "if" => Some(Token::If),
                     "else" => Some(Token::Else),
                     "fn" => Some(Token::Func),
                     "let" => Some(Token::Let),
+                    "mut" => Some(Token::Mut),
+                    "const" => Some(Token::Const),
                     "true" => Some(Token::True),
                     "false" => Some(Token::False),
                     "return" => Some(Token::Ret),
+                    "null" => Some(Token::Null),
+                    "throw" => Some(Token::Throw),
+                    "try" => Some(Token::Try),
+                    "catch" => Some(Token::Catch),
+                    "finally" => Some(Token::Finally),
+                    "struct" => Some(Token::Struct),
+                    "enum" => Some(Token::Enum),
+                    "match" => Some(Token::Match),
                     _ => Some(Token::Identifiere(result)),
                 }
             }
 
-            n if n.is_numeric() => {
-                let result = self.is_number();
-                Some(Token::Integer(
-                    result.parse::<i64>().expect("Failed to parse int"),
-                ))
-            }
+            n if n.is_ascii_digit() => Some(Token::Integer(self.is_number(position)?)),
 
-            _ => None,
-        }
+            c => return Err(LexError::IllegalCharacter(c, position)),
+        };
+
+        Ok(token.map(|t| (t, position)))
     }
 
-    fn is_number(&mut self) -> String {
-        from_fn(|| match self.current {
-            Some(c) if c.is_ascii_digit() => {
+    fn is_number(&mut self, position: Position) -> Result<i64, LexError> {
+        let literal: String = from_fn(|| match self.current {
+            Some(c) if c.is_alphanumeric() || c == '_' => {
                 self.advance_char();
                 Some(c)
             }
             _ => None,
         })
-        .collect()
+        .collect();
+
+        let (radix, digits) = match literal.get(..2) {
+            Some("0x") | Some("0X") => (16, &literal[2..]),
+            Some("0o") | Some("0O") => (8, &literal[2..]),
+            Some("0b") | Some("0B") => (2, &literal[2..]),
+            _ => (10, literal.as_str()),
+        };
+
+        // Separators only go between two digits
+        if digits.is_empty()
+            || digits.starts_with('_')
+            || digits.ends_with('_')
+            || digits.contains("__")
+        {
+            return Err(LexError::InvalidInteger(literal, position));
+        }
+
+        let digits = digits.replace('_', "");
+        i64::from_str_radix(&digits, radix).map_err(|e| match e.kind() {
+            IntErrorKind::PosOverflow => LexError::IntegerOutOfRange(literal.clone(), position),
+            _ => LexError::InvalidInteger(literal.clone(), position),
+        })
     }
 
     fn is_keyword(&mut self) -> String {
@@ -190,6 +350,13 @@ impl<'a> Tokenizer<'a> {
     }
 
     fn advance_char(&mut self) -> Option<char> {
+        if self.current == Some('\n') {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+
         self.current = self.chars.next();
         self.current
     }
@@ -206,24 +373,50 @@ impl<'a> Tokenizer<'a> {
 }
 
 pub struct Lexer {
-    tokens: Vec<Token>,
+    tokens: Vec<(Token, Position)>,
+    position: Position,
+    eof: Position,
 }
 
 impl Lexer {
-    pub fn new(input: &str) -> Box<Self> {
+    pub fn new(input: &str) -> Result<Box<Self>, LexError> {
         let mut tokenizer = Tokenizer::new(input);
         let mut tokens = vec![];
 
-        while let Some(t) = tokenizer.next_token() {
+        while let Some(t) = tokenizer.next_token()? {
             tokens.push(t);
         }
 
         tokens.reverse();
-        Box::new(Self { tokens })
+        let eof = tokenizer.position();
+        Ok(Box::new(Self {
+            tokens,
+            position: eof,
+            eof,
+        }))
     }
 
     pub fn next(&mut self) -> Token {
-        self.tokens.pop().unwrap_or(Token::Eof)
+        match self.tokens.pop() {
+            Some((token, position)) => {
+                self.position = position;
+                token
+            }
+            None => {
+                self.position = self.eof;
+                Token::Eof
+            }
+        }
+    }
+
+    /// Position of the token last returned by `next`, or the end of input
+    pub fn position(&self) -> Position {
+        self.position
+    }
+
+    /// The tokens `next` has yet to return, in order
+    pub fn remaining(&self) -> impl Iterator<Item = &Token> {
+        self.tokens.iter().rev().map(|(token, _)| token)
     }
 }
 
@@ -235,7 +428,7 @@ impl fmt::Display for Token {
 
 #[cfg(test)]
 mod tests {
-    use crate::lexer::{Lexer, Token};
+    use crate::lexer::{LexError, Lexer, Position, Token};
 
     const TEST_STR: &str = "
     let name = \"maro\";
@@ -387,7 +580,7 @@ mod tests {
             Token::Eof,
         ];
 
-        let mut lexer = Lexer::new(TEST_STR);
+        let mut lexer = Lexer::new(TEST_STR).unwrap();
 
         for test_token in test_token_vec.iter() {
             let token = lexer.next();
@@ -398,9 +591,9 @@ mod tests {
     #[test]
     fn simple() {
         let input = "let name = \"marwan\";";
-        let mut lexer = Lexer::new(input);
+        let mut lexer = Lexer::new(input).unwrap();
 
-        let test_tokens = vec![
+        let test_tokens = [
             Token::Let,
             Token::Identifiere(String::from("name")),
             Token::Assign,
@@ -413,4 +606,235 @@ mod tests {
             assert_eq!(token, *test_token);
         }
     }
+
+    #[test]
+    fn test_unicode_identifiers() {
+        let input = "let café = _count + 名前2;";
+        let mut lexer = Lexer::new(input).unwrap();
+
+        let test_tokens = [
+            Token::Let,
+            Token::Identifiere(String::from("café")),
+            Token::Assign,
+            Token::Identifiere(String::from("_count")),
+            Token::Plus,
+            Token::Identifiere(String::from("名前2")),
+            Token::Semicolon,
+            Token::Eof,
+        ];
+
+        for test_token in test_tokens.iter() {
+            let token = lexer.next();
+            assert_eq!(token, *test_token);
+        }
+    }
+
+    #[test]
+    fn test_null_operators() {
+        let input = "null ?? a?[1]?.(b) ? c : d";
+        let mut lexer = Lexer::new(input).unwrap();
+
+        let test_tokens = [
+            Token::Null,
+            Token::NullCoalesce,
+            Token::Identifiere(String::from("a")),
+            Token::QuestionBracket,
+            Token::Integer(1),
+            Token::RBracket,
+            Token::QuestionDot,
+            Token::LParen,
+            Token::Identifiere(String::from("b")),
+            Token::RParen,
+            Token::Question,
+            Token::Identifiere(String::from("c")),
+            Token::Colon,
+            Token::Identifiere(String::from("d")),
+            Token::Eof,
+        ];
+
+        for test_token in test_tokens.iter() {
+            let token = lexer.next();
+            assert_eq!(token, *test_token);
+        }
+    }
+
+    #[test]
+    fn test_ellipsis() {
+        let mut lexer = Lexer::new("f(...rest) 0..n 1..=10 p.x").unwrap();
+
+        let test_tokens = [
+            Token::Identifiere(String::from("f")),
+            Token::LParen,
+            Token::Ellipsis,
+            Token::Identifiere(String::from("rest")),
+            Token::RParen,
+            Token::Integer(0),
+            Token::DotDot,
+            Token::Identifiere(String::from("n")),
+            Token::Integer(1),
+            Token::DotDotEq,
+            Token::Integer(10),
+            Token::Identifiere(String::from("p")),
+            Token::Dot,
+            Token::Identifiere(String::from("x")),
+            Token::Eof,
+        ];
+
+        for test_token in test_tokens.iter() {
+            assert_eq!(lexer.next(), *test_token);
+        }
+    }
+
+    #[test]
+    fn test_match_tokens() {
+        let mut lexer = Lexer::new("mut const enum match (x) { _ => a == b, c = d }").unwrap();
+
+        let test_tokens = [
+            Token::Mut,
+            Token::Const,
+            Token::Enum,
+            Token::Match,
+            Token::LParen,
+            Token::Identifiere(String::from("x")),
+            Token::RParen,
+            Token::LBrace,
+            Token::Identifiere(String::from("_")),
+            Token::FatArrow,
+            Token::Identifiere(String::from("a")),
+            Token::Eq,
+            Token::Identifiere(String::from("b")),
+            Token::Comma,
+            Token::Identifiere(String::from("c")),
+            Token::Assign,
+            Token::Identifiere(String::from("d")),
+            Token::RBrace,
+            Token::Eof,
+        ];
+
+        for test_token in test_tokens.iter() {
+            assert_eq!(lexer.next(), *test_token);
+        }
+    }
+
+    #[test]
+    fn test_illegal_characters() {
+        let test_cases = [
+            ("let x = 5 @ 3;", '@', 1, 11),
+            ("let x = 1;\nlet y = x $ 2;", '$', 2, 11),
+            ("len(x) # 1", '#', 1, 8),
+            ("\n\n  10 % 3", '%', 3, 6),
+            ("let n = ٣;", '٣', 1, 9),
+        ];
+
+        for (input, ch, line, column) in test_cases {
+            let err = Lexer::new(input).err().unwrap();
+            assert_eq!(
+                err,
+                LexError::IllegalCharacter(ch, Position { line, column })
+            );
+        }
+    }
+
+    #[test]
+    fn test_integer_literals() {
+        let input = "42 1_000_000 0x1F 0XfF 0o17 0b1010 0b1_0 9223372036854775807";
+        let mut lexer = Lexer::new(input).unwrap();
+
+        let test_tokens = [
+            Token::Integer(42),
+            Token::Integer(1_000_000),
+            Token::Integer(0x1F),
+            Token::Integer(0xFF),
+            Token::Integer(0o17),
+            Token::Integer(0b1010),
+            Token::Integer(0b10),
+            Token::Integer(i64::MAX),
+            Token::Eof,
+        ];
+
+        for test_token in test_tokens.iter() {
+            let token = lexer.next();
+            assert_eq!(token, *test_token);
+        }
+    }
+
+    #[test]
+    fn test_invalid_integer_literals() {
+        let position = Position { line: 1, column: 9 };
+        let test_cases = [
+            (
+                "let x = 0x;",
+                LexError::InvalidInteger(String::from("0x"), position),
+            ),
+            (
+                "let x = 0b102;",
+                LexError::InvalidInteger(String::from("0b102"), position),
+            ),
+            (
+                "let x = 1_;",
+                LexError::InvalidInteger(String::from("1_"), position),
+            ),
+            (
+                "let x = 0b_1;",
+                LexError::InvalidInteger(String::from("0b_1"), position),
+            ),
+            (
+                "let x = 0x_f;",
+                LexError::InvalidInteger(String::from("0x_f"), position),
+            ),
+            (
+                "let x = 1__000;",
+                LexError::InvalidInteger(String::from("1__000"), position),
+            ),
+            (
+                "let x = 12ab;",
+                LexError::InvalidInteger(String::from("12ab"), position),
+            ),
+            (
+                "let x = 9223372036854775808;",
+                LexError::IntegerOutOfRange(String::from("9223372036854775808"), position),
+            ),
+            (
+                "let x = 0xFFFFFFFFFFFFFFFFF;",
+                LexError::IntegerOutOfRange(String::from("0xFFFFFFFFFFFFFFFFF"), position),
+            ),
+        ];
+
+        for (input, expected) in test_cases {
+            assert_eq!(Lexer::new(input).err().unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_bitwise_operators() {
+        let input = "a & b | c ^ ~d << 2 >> 1 < 3 > 4 |> f";
+        let mut lexer = Lexer::new(input).unwrap();
+
+        let test_tokens = [
+            Token::Identifiere(String::from("a")),
+            Token::Ampersand,
+            Token::Identifiere(String::from("b")),
+            Token::Pipe,
+            Token::Identifiere(String::from("c")),
+            Token::Caret,
+            Token::Tilde,
+            Token::Identifiere(String::from("d")),
+            Token::ShiftLeft,
+            Token::Integer(2),
+            Token::ShiftRight,
+            Token::Integer(1),
+            Token::Lt,
+            Token::Integer(3),
+            Token::Gt,
+            Token::Integer(4),
+            Token::Pipeline,
+            Token::Identifiere(String::from("f")),
+            Token::Eof,
+        ];
+
+        for test_token in test_tokens.iter() {
+            let token = lexer.next();
+            assert_eq!(token, *test_token);
+        }
+    }
 }
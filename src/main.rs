@@ -1,21 +1,79 @@
 mod ast;
 mod enviroment;
 mod evaluator;
+mod gc;
 mod inbuilt;
 mod lexer;
 pub mod object;
 mod parser;
+mod resolver;
+mod symbol;
 
 use crate::enviroment::EnviromentVariables;
-use crate::evaluator::eval_program;
+use crate::evaluator::{eval_program, RuntimeError};
 use crate::lexer::Lexer;
+use crate::object::Object;
 use linefeed::{Interface, ReadResult};
 use std::cell::RefCell;
 use std::rc::Rc;
+use std::{env, fs, process, thread};
 
-fn main() {
+/// The evaluator recurses on the native stack, so it runs on a thread with
+/// enough room for `evaluator::MAX_CALL_DEPTH` nested calls
+const INTERPRETER_STACK_SIZE: usize = 256 * 1024 * 1024;
+
+enum RunError {
+    Lex(lexer::LexError),
+    Runtime(RuntimeError),
+}
+
+/// Flag that reports what the resolver warns about, like shadowed variables
+const WARN_FLAG: &str = "--warn";
+
+fn run(
+    input: &str,
+    env: &mut Rc<RefCell<EnviromentVariables>>,
+    warn: bool,
+) -> Result<Object, RunError> {
+    let lexer = Lexer::new(input).map_err(RunError::Lex)?;
+    let mut parser = parser::Parser::new(lexer);
+
+    let program = parser.parse_program().unwrap();
+    let warnings = resolver::resolve_program(&program).map_err(RunError::Runtime)?;
+    if warn {
+        for warning in warnings {
+            eprintln!("{}", warning);
+        }
+    }
+    eval_program(program.as_ref(), env).map_err(RunError::Runtime)
+}
+
+fn report(err: &RunError) -> String {
+    match err {
+        RunError::Lex(err) => err.to_string(),
+        RunError::Runtime(err) => err.traceback(),
+    }
+}
+
+fn run_file(path: &str, warn: bool) {
+    let input = match fs::read_to_string(path) {
+        Ok(input) => input,
+        Err(err) => {
+            eprintln!("Unable to read {}: {}", path, err);
+            process::exit(1);
+        }
+    };
+
+    let mut env = gc::allocate(EnviromentVariables::new());
+    if let Err(err) = run(&input, &mut env, warn) {
+        eprintln!("{}", report(&err));
+        process::exit(1);
+    }
+}
+
+fn run_repl(warn: bool) {
     let reader = Interface::new("Crimson lang").unwrap();
-    let mut env = Rc::new(RefCell::new(EnviromentVariables::new()));
+    let mut env = gc::allocate(EnviromentVariables::new());
 
     println!("Crimson Lang. \n");
     reader.set_prompt("> ").unwrap();
@@ -24,11 +82,29 @@ fn main() {
         if input.eq("exit") {
             break;
         }
-        let lexer = Lexer::new(&input);
-        let mut parser = parser::Parser::new(lexer);
 
-        let program = parser.parse_program().unwrap();
-        println!("{}", eval_program(program.as_ref(), &mut env));
+        match run(&input, &mut env, warn) {
+            Ok(val) => println!("{}", val),
+            Err(err) => println!("{}", report(&err)),
+        }
     }
     println!("Bye !");
 }
+
+fn main() {
+    let (flags, paths): (Vec<String>, Vec<String>) =
+        env::args().skip(1).partition(|arg| arg == WARN_FLAG);
+    let warn = !flags.is_empty();
+    let path = paths.into_iter().next();
+    let interpreter = thread::Builder::new()
+        .stack_size(INTERPRETER_STACK_SIZE)
+        .spawn(move || match path {
+            Some(path) => run_file(&path, warn),
+            None => run_repl(warn),
+        })
+        .unwrap();
+
+    if interpreter.join().is_err() {
+        process::exit(101);
+    }
+}
@@ -1,44 +1,243 @@
-use crate::ast::BlockStatement;
+use crate::ast::{join, EnumDef, Function, StructDef};
 use crate::enviroment::EnviromentVariables;
+use crate::evaluator::RuntimeError;
+use crate::gc;
+use indexmap::IndexMap;
 use std::cell::RefCell;
-use std::collections::HashMap;
-use std::fmt::Display;
-use std::hash::{Hash, Hasher};
+use std::fmt::{Display, Formatter};
 use std::rc::Rc;
 
+pub type ArrayRef = Rc<RefCell<Vec<Object>>>;
+pub type TupleRef = Rc<[Object]>;
+pub type StructRef = Rc<Instance>;
+pub type HashMapRef = Rc<RefCell<IndexMap<HashKey, Object>>>;
+
+thread_local! {
+    /// Containers being displayed, so one that contains itself is not
+    /// displayed forever
+    static DISPLAYING: RefCell<Vec<*const ()>> = const { RefCell::new(Vec::new()) };
+}
+
+///
+///  A value of a declared struct, with its fields in declaration order.
+///  Like arrays, instances are shared and their fields can be assigned. Its
+///  methods are closures over `env`, the scope the struct was declared in
+///
+#[derive(Debug)]
+pub struct Instance {
+    pub def: Rc<StructDef>,
+    pub env: Rc<RefCell<EnviromentVariables>>,
+    pub fields: RefCell<Vec<Object>>,
+}
+
+impl PartialEq for Instance {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.def, &other.def) && self.fields == other.fields
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum Object {
-    Err(String),
+    Err(RuntimeError),
     Null,
     Integer(i64),
     Bool(bool),
     String(String),
     Identifier(String),
     BuiltInFunction(String),
-    Array(Vec<Object>),
-    HashMap(HashMap<Object, Object>),
+    Array(ArrayRef),
+    Tuple(TupleRef),
+    /// The integers from the first up to, but not including, the second
+    Range(i64, i64),
+    HashMap(HashMapRef),
+    StructType(Rc<StructDef>, Rc<RefCell<EnviromentVariables>>),
+    Struct(StructRef),
+    EnumType(Rc<EnumDef>),
+    /// A variant of an enum, by its position in the declaration, and the
+    /// values it holds
+    Variant(Rc<EnumDef>, usize, TupleRef),
     FunctionLiteral(
-        Vec<String>,
-        BlockStatement,
+        Option<String>,
+        Rc<Function>,
         Rc<RefCell<EnviromentVariables>>,
     ),
+    /// The function `f >> g` gives, which calls `f` and then `g` with the
+    /// result
+    Composition(Box<Object>, Box<Object>),
+}
+
+///
+///  How many integers `start..end` holds, none when `end` is not after `start`
+///
+pub fn range_len(start: i64, end: i64) -> usize {
+    if end > start {
+        end.abs_diff(start) as usize
+    } else {
+        0
+    }
+}
+
+impl Object {
+    ///
+    ///  Arrays and hashmaps are shared, so assigning or passing one hands out
+    ///  another reference to the same heap object
+    ///
+    pub fn array(items: Vec<Object>) -> Object {
+        Object::Array(gc::allocate_array(items))
+    }
+
+    pub fn tuple(items: Vec<Object>) -> Object {
+        Object::Tuple(gc::allocate_tuple(items))
+    }
+
+    pub fn instance(
+        def: Rc<StructDef>,
+        env: Rc<RefCell<EnviromentVariables>>,
+        fields: Vec<Object>,
+    ) -> Object {
+        Object::Struct(gc::allocate_struct(Instance {
+            def,
+            env,
+            fields: RefCell::new(fields),
+        }))
+    }
+
+    pub fn variant(def: Rc<EnumDef>, variant: usize, values: Vec<Object>) -> Object {
+        Object::Variant(def, variant, gc::allocate_tuple(values))
+    }
+
+    pub fn hashmap(entries: IndexMap<HashKey, Object>) -> Object {
+        Object::HashMap(gc::allocate_hashmap(entries))
+    }
+
+    ///
+    ///  The items of an array, tuple, range or string, one character
+    ///  strings for the latter. Ranges are produced lazily, and an array is
+    ///  copied first so it can change while its items are used
+    ///
+    pub fn items(&self) -> Option<Box<dyn Iterator<Item = Object> + '_>> {
+        match self {
+            Object::Array(items) => Some(Box::new(items.borrow().clone().into_iter())),
+            Object::Tuple(items) => Some(Box::new(items.iter().cloned())),
+            Object::Range(start, end) => Some(Box::new((*start..*end).map(Object::Integer))),
+            Object::String(s) => Some(Box::new(s.chars().map(|c| Object::String(c.to_string())))),
+            _ => None,
+        }
+    }
+
+    ///
+    ///  The key this value stores under in a hashmap. Mutable values can't be
+    ///  keys, so for one of them, or a tuple holding one, this returns the
+    ///  mutable value
+    ///
+    pub fn hash_key(&self) -> Result<HashKey, &Object> {
+        match self {
+            Object::Null => Ok(HashKey::Null),
+            Object::Bool(b) => Ok(HashKey::Bool(*b)),
+            Object::Integer(i) => Ok(HashKey::Integer(*i)),
+            Object::String(s) => Ok(HashKey::String(s.clone())),
+            Object::Tuple(items) => items
+                .iter()
+                .map(Object::hash_key)
+                .collect::<Result<_, _>>()
+                .map(HashKey::Tuple),
+            _ => Err(self),
+        }
+    }
+
+    pub fn is_callable(&self) -> bool {
+        matches!(
+            self,
+            Object::FunctionLiteral(..) | Object::BuiltInFunction(_) | Object::Composition(..)
+        )
+    }
+
+    ///
+    ///  Calls `f` with the heap object this value refers to, if any
+    ///
+    pub fn for_each_reference(&self, f: &mut dyn FnMut(*const ())) {
+        match self {
+            Object::Composition(first, second) => {
+                first.for_each_reference(f);
+                second.for_each_reference(f);
+            }
+            Object::FunctionLiteral(.., env) | Object::StructType(_, env) => {
+                f(Rc::as_ptr(env) as *const ())
+            }
+            Object::Array(items) => f(Rc::as_ptr(items) as *const ()),
+            Object::Tuple(items) | Object::Variant(.., items) => f(Rc::as_ptr(items) as *const ()),
+            Object::HashMap(map) => f(Rc::as_ptr(map) as *const ()),
+            Object::Struct(instance) => f(Rc::as_ptr(instance) as *const ()),
+            _ => {}
+        }
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Object::Err(_) => "error",
+            Object::Null => "null",
+            Object::Integer(_) => "integer",
+            Object::Bool(_) => "boolean",
+            Object::String(_) => "string",
+            Object::Identifier(_) => "identifier",
+            Object::BuiltInFunction(_) => "builtin function",
+            Object::Array(_) => "array",
+            Object::Tuple(_) => "tuple",
+            Object::Range(..) => "range",
+            Object::HashMap(_) => "hashmap",
+            Object::StructType(..) => "struct",
+            Object::Struct(_) => "struct instance",
+            Object::EnumType(_) => "enum",
+            Object::Variant(..) => "enum variant",
+            Object::FunctionLiteral(..) | Object::Composition(..) => "function",
+        }
+    }
+}
+
+///
+///  A validated hashmap key. Only immutable values can be keys, so a key can
+///  never change after it is inserted
+///
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum HashKey {
+    Null,
+    Bool(bool),
+    Integer(i64),
+    String(String),
+    Tuple(Vec<HashKey>),
 }
 
-impl Eq for Object {}
+impl HashKey {
+    pub fn to_object(&self) -> Object {
+        match self {
+            HashKey::Null => Object::Null,
+            HashKey::Bool(b) => Object::Bool(*b),
+            HashKey::Integer(i) => Object::Integer(*i),
+            HashKey::String(s) => Object::String(s.clone()),
+            HashKey::Tuple(items) => Object::tuple(items.iter().map(HashKey::to_object).collect()),
+        }
+    }
+}
 
-impl Hash for Object {
-    fn hash<H: Hasher>(&self, state: &mut H) {
+impl Display for HashKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
-            Object::String(s) => s.hash(state),
-            Object::Integer(i) => i.hash(state),
-            _ => panic!(
-                "Invalid Hash key {}, only string and integer are allowed",
-                self
-            ),
+            HashKey::Null => write!(f, "NULL"),
+            HashKey::Bool(b) => write!(f, "{}", b),
+            HashKey::Integer(i) => write!(f, "{}", i),
+            HashKey::String(s) => write!(f, "\"{}\"", s),
+            HashKey::Tuple(items) => write_tuple(f, items),
         }
     }
 }
 
+fn write_tuple<T: Display>(f: &mut Formatter<'_>, items: &[T]) -> std::fmt::Result {
+    match items {
+        [item] => write!(f, "({},)", item),
+        _ => write!(f, "({})", join(items)),
+    }
+}
+
 impl Display for Object {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -48,31 +247,92 @@ impl Display for Object {
             Object::Bool(b) => write!(f, "{}", b),
             Object::Identifier(s) => write!(f, "{}", s),
             Object::String(s) => write!(f, "\"{}\"", s),
-            Object::Array(arr) => write!(
-                f,
-                "[{}]",
-                arr.iter()
-                    .map(|a| a.to_string())
-                    .collect::<Vec<String>>()
-                    .join(",")
-            ),
+            Object::Array(arr) => write_container(f, Rc::as_ptr(arr) as *const (), "[...]", |f| {
+                write!(
+                    f,
+                    "[{}]",
+                    arr.borrow()
+                        .iter()
+                        .map(|a| a.to_string())
+                        .collect::<Vec<String>>()
+                        .join(",")
+                )
+            }),
+            Object::Tuple(items) => write_tuple(f, items),
+            Object::Range(start, end) => write!(f, "{}..{}", start, end),
             Object::HashMap(dict) => {
-                let mut str = String::new();
-                str.push('{');
-                for (k, v) in dict {
-                    str.push_str(format!("{}:{},", k, v).as_str());
-                }
+                write_container(f, Rc::as_ptr(dict) as *const (), "{...}", |f| {
+                    let mut str = String::new();
+                    str.push('{');
+                    for (k, v) in dict.borrow().iter() {
+                        str.push_str(format!("{}:{},", k, v).as_str());
+                    }
 
-                if str.ends_with(',') {
-                    str.pop();
+                    if str.ends_with(',') {
+                        str.pop();
+                    }
+                    str.push('}');
+                    write!(f, "{}", str)
+                })
+            }
+            Object::StructType(def, _) => write!(f, "{}", def),
+            Object::Struct(instance) => {
+                write_container(f, Rc::as_ptr(instance) as *const (), "...", |f| {
+                    let fields: Vec<String> = instance
+                        .def
+                        .fields
+                        .iter()
+                        .zip(instance.fields.borrow().iter())
+                        .map(|(field, value)| format!("{}: {}", field, value))
+                        .collect();
+                    write!(f, "{} {{ {} }}", instance.def.name, join(&fields))
+                })
+            }
+            Object::EnumType(def) => write!(f, "{}", def),
+            Object::Variant(def, variant, values) => {
+                write!(f, "{}.{}", def.name, def.variants[*variant].0)?;
+                match values.is_empty() {
+                    true => Ok(()),
+                    false => write!(f, "({})", join(values)),
                 }
-                str.push('}');
-                write!(f, "{}", str)
             }
-            Object::FunctionLiteral(parameters, block, _) => {
-                write!(f, "fn({}){{ {} }}", parameters.join(","), block)
+            Object::FunctionLiteral(Some(name), function, _) => {
+                write!(
+                    f,
+                    "fn {}({}){{ {} }}",
+                    name,
+                    join(&function.params),
+                    function.body
+                )
             }
-            _ => panic!("Invalid object"),
+            Object::FunctionLiteral(None, function, _) => {
+                write!(f, "fn({}){{ {} }}", join(&function.params), function.body)
+            }
+            Object::BuiltInFunction(name) => write!(f, "<builtin {}>", name),
+            Object::Composition(first, second) => write!(f, "{} >> {}", first, second),
+        }
+    }
+}
+
+fn write_container(
+    f: &mut Formatter<'_>,
+    container: *const (),
+    placeholder: &str,
+    write: impl FnOnce(&mut Formatter<'_>) -> std::fmt::Result,
+) -> std::fmt::Result {
+    let nested = DISPLAYING.with(|displaying| {
+        let mut displaying = displaying.borrow_mut();
+        let nested = displaying.contains(&container);
+        if !nested {
+            displaying.push(container);
         }
+        nested
+    });
+    if nested {
+        return write!(f, "{}", placeholder);
     }
+
+    let result = write(f);
+    DISPLAYING.with(|displaying| displaying.borrow_mut().pop());
+    result
 }
@@ -1,13 +1,24 @@
 use crate::ast::*;
-use crate::lexer::{Lexer, Token};
+use crate::lexer::{Lexer, Position, Token};
+use crate::symbol::Symbol;
 use std::fmt;
 use std::fmt::Debug;
+use std::rc::Rc;
 
-#[derive(Debug, PartialEq, Eq, Clone, PartialOrd)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy, PartialOrd)]
 pub enum Precedence {
     Lowest,
+    Assign,
+    Ternary,
+    Pipeline,
+    NullCoalesce,
+    Range,
     Equals,
     LessGreater,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Shift,
     Sum,
     Product,
     Prefix,
@@ -29,23 +40,46 @@ impl fmt::Display for ParseError {
 pub(crate) struct Parser {
     lexer: Box<Lexer>,
     curr_token: Token,
+    curr_position: Position,
     next_token: Token,
+    next_position: Position,
+    /// How many `(`, `[` and `{` enclose the current token
+    depth: usize,
+    /// The depths of the hashmap keys, slice starts and first ternary
+    /// branches being parsed, each of which a `:` of its own ends
+    colon_claims: Vec<usize>,
 }
 
 impl Parser {
     pub fn new(mut lexer: Box<Lexer>) -> Box<Parser> {
         let curr_token = lexer.next();
+        let curr_position = lexer.position();
         let next_token = lexer.next();
+        let next_position = lexer.position();
         Box::new(Parser {
             lexer,
             curr_token,
+            curr_position,
             next_token,
+            next_position,
+            depth: 0,
+            colon_claims: vec![],
         })
     }
 
     pub fn next(&mut self) -> Token {
+        if let Token::LParen | Token::LBracket | Token::QuestionBracket | Token::LBrace =
+            self.curr_token
+        {
+            self.depth += 1;
+        }
+        if let Token::RParen | Token::RBracket | Token::RBrace = self.next_token {
+            self.depth = self.depth.saturating_sub(1);
+        }
         self.curr_token = self.next_token.clone();
+        self.curr_position = self.next_position;
         self.next_token = self.lexer.next();
+        self.next_position = self.lexer.position();
         self.curr_token.clone()
     }
 
@@ -55,22 +89,89 @@ impl Parser {
 
     pub fn precedence(&self, token: &Token) -> Precedence {
         match token {
+            Token::Assign => Precedence::Assign,
+            Token::Question => Precedence::Ternary,
+            Token::Pipeline => Precedence::Pipeline,
+            Token::NullCoalesce => Precedence::NullCoalesce,
+            Token::DotDot => Precedence::Range,
+            Token::DotDotEq => Precedence::Range,
             Token::Eq => Precedence::Equals,
             Token::NotEq => Precedence::Equals,
             Token::Lt => Precedence::LessGreater,
             Token::Gt => Precedence::LessGreater,
+            Token::Pipe => Precedence::BitOr,
+            Token::Caret => Precedence::BitXor,
+            Token::Ampersand => Precedence::BitAnd,
+            Token::ShiftLeft => Precedence::Shift,
+            Token::ShiftRight => Precedence::Shift,
             Token::Plus => Precedence::Sum,
             Token::Minus => Precedence::Sum,
             Token::Asterisk => Precedence::Product,
             Token::Slash => Precedence::Product,
             Token::LParen => Precedence::Call,
+            Token::QuestionDot => Precedence::Call,
+            Token::Dot => Precedence::Call,
             Token::LBracket => Precedence::Index,
+            Token::QuestionBracket => Precedence::Index,
             _ => Precedence::Lowest,
         }
     }
 
     pub fn peek_precedence(&self) -> Precedence {
-        self.precedence(&self.next_token)
+        match self.next_token {
+            Token::QuestionBracket if self.starts_ternary_branch(self.lexer.remaining()) => {
+                Precedence::Ternary
+            }
+            _ => self.precedence(&self.next_token),
+        }
+    }
+
+    ///
+    ///  Whether `tokens`, which follow a `?[`, make it the `?` of a ternary
+    ///  whose first branch is an array, like `c ?[1] : [2]`, rather than an
+    ///  optional index. It is when a `:` follows that neither a ternary after
+    ///  it nor a hashmap key, slice or ternary around it claims
+    ///
+    fn starts_ternary_branch<'a>(&self, tokens: impl Iterator<Item = &'a Token>) -> bool {
+        let claimed = self
+            .colon_claims
+            .iter()
+            .filter(|&&depth| depth == self.depth);
+        let claimed = claimed.count();
+
+        // Each `?` of a ternary has a `:` of its own, and a later `?[` may be
+        // one too, which takes the nearest `:` like nested ternaries do
+        let mut ternaries = 0;
+        let mut unclaimed = 0;
+        let mut depth = 1;
+        for token in tokens {
+            match token {
+                Token::QuestionBracket if depth == 0 => {
+                    ternaries += 1;
+                    depth += 1
+                }
+                Token::LParen | Token::LBracket | Token::QuestionBracket | Token::LBrace => {
+                    depth += 1
+                }
+                Token::RParen | Token::RBracket | Token::RBrace if depth == 0 => break,
+                Token::RParen | Token::RBracket | Token::RBrace => depth -= 1,
+                Token::Question if depth == 0 => ternaries += 1,
+                Token::Colon if depth == 0 && ternaries > 0 => ternaries -= 1,
+                Token::Colon if depth == 0 => unclaimed += 1,
+                Token::Semicolon | Token::Comma if depth == 0 => break,
+                Token::Eof => break,
+                _ => {}
+            }
+        }
+        unclaimed > claimed
+    }
+
+    /// Parses an expression that a `:` of its own ends, like a hashmap key
+    fn parse_before_colon(&mut self) -> Box<Expression> {
+        self.colon_claims.push(self.depth);
+        let expr = self.parse_expression(Precedence::Lowest);
+        self.colon_claims.pop();
+        expr
     }
 
     pub fn expect_current_token(&mut self, token: Token) {
@@ -96,18 +197,30 @@ impl Parser {
         }
     }
 
+    ///
+    ///  Parses `let name = value;`, or a destructuring `let` whose target is
+    ///  a pattern like `[a, b, ...rest]` or `{name, age}`. `let mut` and
+    ///  `const` statements take the same form
+    ///
     fn parse_let_statement(&mut self) -> Box<Statement> {
-        let token = self.next();
-        let identifier = match token {
-            Token::Identifiere(s) => Expression::Identifier(s),
-            _ => panic!("Identifier token not found in let statement {}", token),
-        };
+        let position = self.curr_position;
+        let constant = self.curr_token == Token::Const;
+        let mutable = !constant && self.peek() == Token::Mut;
+        if mutable {
+            self.next();
+        }
+        self.next();
+        let pattern = self.parse_pattern();
 
         self.expect_next_token(Token::Assign);
         self.next();
 
         let expr = self.parse_expression(Precedence::Lowest);
-        let let_stmt = Statement::Let(identifier.to_string(), expr);
+        let let_stmt = match (constant, mutable) {
+            (true, _) => Statement::Const(pattern, expr, position),
+            (false, true) => Statement::LetMut(pattern, expr, position),
+            (false, false) => Statement::Let(pattern, expr, position),
+        };
 
         if self.peek() == Token::Semicolon {
             self.next();
@@ -116,6 +229,19 @@ impl Parser {
         Box::new(let_stmt)
     }
 
+    fn parse_throw_statement(&mut self) -> Box<Statement> {
+        let position = self.curr_position;
+        self.next();
+
+        let expr = self.parse_expression(Precedence::Lowest);
+
+        if self.peek() == Token::Semicolon {
+            self.next();
+        }
+
+        Box::new(Statement::Throw(expr, position))
+    }
+
     fn parse_return_statement(&mut self) -> Box<Statement> {
         self.next();
 
@@ -136,7 +262,9 @@ impl Parser {
         let curr_token = &self.curr_token;
 
         match curr_token {
-            Token::Identifiere(s) => Box::new(Expression::Identifier(s.to_string())),
+            Token::Identifiere(s) => {
+                Box::new(Expression::Identifier(Variable::new(s), self.curr_position))
+            }
             _ => panic!("Unable to parse identifier {}", self.curr_token),
         }
     }
@@ -171,10 +299,12 @@ impl Parser {
 
     fn parse_prefix_expression(&mut self) -> Box<Expression> {
         let op = self.curr_token.clone();
+        let position = self.curr_position;
 
         let prefix = match op {
             Token::Bang => Prefix::Bang,
             Token::Minus => Prefix::Minus,
+            Token::Tilde => Prefix::Tilde,
             _ => panic!("Invalid token {} prefix expression", op),
         };
 
@@ -182,35 +312,361 @@ impl Parser {
         Box::new(Expression::Prefix(
             prefix,
             self.parse_expression(Precedence::Prefix),
+            position,
         ))
     }
 
+    ///
+    ///  Parses a parenthesized expression, or a tuple when the parentheses
+    ///  are empty or hold a comma: `()`, `(a,)` and `(a, b)`
+    ///
     fn parse_group_expression(&mut self) -> Box<Expression> {
         self.expect_current_token(Token::LParen);
+        if self.curr_token == Token::RParen {
+            return Box::new(Expression::TupleLiteral(vec![]));
+        }
+
         let expr = self.parse_expression(Precedence::Lowest);
+        if self.peek() != Token::Comma {
+            self.expect_next_token(Token::RParen);
+            return expr;
+        }
+
+        let mut members = vec![*expr];
+        while self.peek() == Token::Comma {
+            self.next();
+            if self.peek() == Token::RParen {
+                break;
+            }
+            self.next();
+            members.push(*self.parse_expression(Precedence::Lowest));
+        }
         self.expect_next_token(Token::RParen);
-        expr
+        Box::new(Expression::TupleLiteral(members))
     }
 
+    ///
+    ///  Parses an `if` and the `else if` branches following it into a flat
+    ///  chain, leaving the `}` of its last block the current token
+    ///
     fn parse_if_expression(&mut self) -> Box<Expression> {
-        self.expect_current_token(Token::If);
-        let condition = self.parse_group_expression();
-        let true_block = self.parse_block_statement();
+        let mut branches = vec![];
+        let mut false_block: Option<Box<BlockStatement>> = None;
+        loop {
+            self.expect_current_token(Token::If);
+            let position = self.next_position;
+            let condition = self.parse_group_expression();
+            let block = self.parse_block_statement();
+            branches.push((*condition, position, *block));
+
+            if self.peek() != Token::Else {
+                break;
+            }
+            self.next();
+            if self.peek() != Token::If {
+                false_block = Some(self.parse_block_statement());
+                break;
+            }
+            self.next();
+        }
+
+        Box::new(Expression::If(branches, false_block))
+    }
+
+    ///
+    ///  Parses `condition ? a : b`. It groups to the right, so
+    ///  `a ? b : c ? d : e` chains like `else if`
+    ///
+    fn parse_ternary(&mut self, condition: Expression, position: Position) -> Box<Expression> {
         self.next();
+        self.parse_ternary_branches(condition, position)
+    }
 
-        let mut false_block: Option<Box<BlockStatement>> = None;
-        if self.curr_token == Token::Else {
-            false_block = Some(self.parse_block_statement());
+    fn parse_ternary_branches(
+        &mut self,
+        condition: Expression,
+        position: Position,
+    ) -> Box<Expression> {
+        let true_expr = self.parse_before_colon();
+        self.expect_next_token(Token::Colon);
+        self.next();
+        let false_expr = self.parse_expression(Precedence::Assign);
+
+        Box::new(Expression::Ternary(
+            Box::new(condition),
+            true_expr,
+            false_expr,
+            position,
+        ))
+    }
+
+    fn parse_try_expression(&mut self) -> Box<Expression> {
+        let body = self.parse_block_statement();
+
+        let mut catch = None;
+        if self.peek() == Token::Catch {
             self.next();
+            self.expect_next_token(Token::LParen);
+            let name = match self.next() {
+                Token::Identifiere(s) => s,
+                token => panic!("Expected identifier in catch clause, found {}", token),
+            };
+            self.expect_next_token(Token::RParen);
+            catch = Some(CatchClause {
+                name: Variable::new(&name),
+                body: self.parse_block_statement(),
+                locals: Locals::default(),
+            });
+        }
+
+        let mut finally = None;
+        if self.peek() == Token::Finally {
+            self.next();
+            finally = Some(self.parse_block_statement());
+        }
+
+        if catch.is_none() && finally.is_none() {
+            panic!(
+                "Expected catch or finally after try block, found {}",
+                self.peek()
+            );
         }
 
-        Box::new(Expression::If(condition, true_block, false_block))
+        Box::new(Expression::Try(body, catch, finally))
     }
 
-    fn parse_block_statement(&mut self) -> Box<BlockStatement> {
-        let mut statements: Vec<Statement> = vec![];
+    ///
+    ///  Parses `match (subject) { pattern if guard => body, .. }`, where a
+    ///  body is a block or a single expression. The comma after an arm is
+    ///  optional when its body is a block
+    ///
+    fn parse_match_expression(&mut self) -> Box<Expression> {
+        let position = self.curr_position;
+        self.expect_current_token(Token::Match);
+        let subject = self.parse_group_expression();
+        self.expect_next_token(Token::LBrace);
+        self.next();
+
+        let mut arms = vec![];
+        while self.curr_token != Token::RBrace {
+            let pattern = self.parse_pattern();
+
+            let guard = match self.peek() {
+                Token::If => {
+                    self.next();
+                    self.next();
+                    Some(*self.parse_expression(Precedence::Lowest))
+                }
+                _ => None,
+            };
+            self.expect_next_token(Token::FatArrow);
+
+            let block_body = self.peek() == Token::LBrace;
+            let body = match block_body {
+                true => *self.parse_block_statement(),
+                false => {
+                    self.next();
+                    let expr = self.parse_expression(Precedence::Lowest);
+                    BlockStatement {
+                        stmts: vec![Statement::Expression(expr)],
+                        locals: Locals::default(),
+                    }
+                }
+            };
+            arms.push(MatchArm {
+                pattern,
+                guard,
+                body,
+                locals: Locals::default(),
+            });
+
+            if self.peek() == Token::Comma || (!block_body && self.peek() != Token::RBrace) {
+                self.expect_next_token(Token::Comma);
+            }
+            self.next();
+        }
+
+        Box::new(Expression::Match(subject, arms, position))
+    }
+
+    ///
+    ///  Parses a pattern that binds each variable at most once, leaving the
+    ///  current token on its last token
+    ///
+    fn parse_pattern(&mut self) -> Pattern {
+        let pattern = self.parse_subpattern();
+        let mut bindings: Vec<Symbol> = vec![];
+        for variable in pattern.bindings() {
+            if bindings.contains(&variable.name) {
+                panic!("Duplicate binding {} in pattern {}", variable, pattern);
+            }
+            bindings.push(variable.name);
+        }
+        pattern
+    }
+
+    fn parse_subpattern(&mut self) -> Pattern {
+        let position = self.curr_position;
+        match self.curr_token.clone() {
+            Token::Identifiere(name) if name == "_" => Pattern::Wildcard,
+            Token::Identifiere(name) if self.peek() == Token::Dot => {
+                self.next();
+                let variant = match self.next() {
+                    Token::Identifiere(variant) => Symbol::intern(&variant),
+                    token => panic!("Expected a variant name after {}., found {}", name, token),
+                };
+                let items = match self.peek() {
+                    Token::LParen => {
+                        self.next();
+                        self.parse_pattern_list(Token::RParen)
+                    }
+                    _ => vec![],
+                };
+                Pattern::Variant(Variable::new(&name), variant, items, position)
+            }
+            Token::Identifiere(name) if self.peek() == Token::LBrace => {
+                self.next();
+                let fields = self
+                    .parse_pattern_entries()
+                    .into_iter()
+                    .map(|(field, pattern)| match field {
+                        Expression::String(field) => (Symbol::intern(&field), pattern),
+                        field => {
+                            panic!("Expected a field name in {} pattern, found {}", name, field)
+                        }
+                    })
+                    .collect();
+                Pattern::Struct(Variable::new(&name), fields, position)
+            }
+            Token::Identifiere(name) => Pattern::Binding(Variable::new(&name)),
+            Token::LBracket => {
+                let mut items = vec![];
+                let mut rest = None;
+                self.next();
+                while self.curr_token != Token::RBracket {
+                    if self.curr_token == Token::Ellipsis {
+                        rest = Some(Box::new(match self.peek() {
+                            Token::RBracket => Pattern::Wildcard,
+                            _ => {
+                                self.next();
+                                self.parse_subpattern()
+                            }
+                        }));
+                        self.expect_next_token(Token::RBracket);
+                        break;
+                    }
+
+                    items.push(self.parse_subpattern());
+                    if self.peek() != Token::RBracket {
+                        self.expect_next_token(Token::Comma);
+                    }
+                    self.next();
+                }
+                Pattern::Array(items, rest)
+            }
+            Token::LParen => {
+                // Like in expressions, `(a)` is just `a` while `(a,)` is a tuple
+                self.next();
+                if self.curr_token == Token::RParen {
+                    return Pattern::Tuple(vec![]);
+                }
+                let pattern = self.parse_subpattern();
+                if self.peek() != Token::Comma {
+                    self.expect_next_token(Token::RParen);
+                    return pattern;
+                }
+
+                let mut items = vec![pattern];
+                while self.peek() == Token::Comma {
+                    self.next();
+                    if self.peek() == Token::RParen {
+                        break;
+                    }
+                    self.next();
+                    items.push(self.parse_subpattern());
+                }
+                self.expect_next_token(Token::RParen);
+                Pattern::Tuple(items)
+            }
+            Token::LBrace => Pattern::HashMap(self.parse_pattern_entries()),
+            _ => Pattern::Literal(self.parse_pattern_literal()),
+        }
+    }
+
+    /// Parses `(a, b)`, leaving the current token on the closing `end`
+    fn parse_pattern_list(&mut self, end: Token) -> Vec<Pattern> {
+        let mut items = vec![];
+        self.next();
+        while self.curr_token != end {
+            items.push(self.parse_subpattern());
+            if self.peek() != end {
+                self.expect_next_token(Token::Comma);
+            }
+            self.next();
+        }
+        items
+    }
+
+    ///
+    ///  Parses the `{key: pattern, name}` of a hashmap or struct pattern,
+    ///  where a name without a pattern binds the variable of the same name
+    ///  and a name as a key stands for the string of it
+    ///
+    fn parse_pattern_entries(&mut self) -> Vec<(Expression, Pattern)> {
+        let mut entries: Vec<(Expression, Pattern)> = vec![];
+        self.expect_current_token(Token::LBrace);
+        while self.curr_token != Token::RBrace {
+            let key = match self.curr_token.clone() {
+                Token::Identifiere(name) => Expression::String(name),
+                _ => self.parse_pattern_literal(),
+            };
+            let pattern = match (&self.curr_token, self.peek()) {
+                (_, Token::Colon) => {
+                    self.next();
+                    self.next();
+                    self.parse_subpattern()
+                }
+                (Token::Identifiere(name), _) => Pattern::Binding(Variable::new(name)),
+                _ => panic!("Expected : after key {} in pattern", key),
+            };
+
+            if entries.iter().any(|(k, _)| *k == key) {
+                panic!("Duplicate key {} in pattern", key);
+            }
+            entries.push((key, pattern));
 
+            if self.peek() != Token::RBrace {
+                self.expect_next_token(Token::Comma);
+            }
+            self.next();
+        }
+        entries
+    }
+
+    /// An integer, optionally negative, string, boolean or null literal
+    fn parse_pattern_literal(&mut self) -> Expression {
+        match self.curr_token.clone() {
+            Token::Integer(i) => Expression::IntegerLiteral(i),
+            Token::Minus => match self.next() {
+                Token::Integer(i) => Expression::IntegerLiteral(-i),
+                token => panic!("Expected an integer after - in pattern, found {}", token),
+            },
+            Token::String(s) => Expression::String(s),
+            Token::True => Expression::Bool(true),
+            Token::False => Expression::Bool(false),
+            Token::Null => Expression::Null,
+            token => panic!("Invalid token in pattern {}", token),
+        }
+    }
+
+    fn parse_block_statement(&mut self) -> Box<BlockStatement> {
         self.expect_next_token(Token::LBrace);
+        self.parse_block()
+    }
+
+    /// Parses the statements of the block the current `{` opens
+    fn parse_block(&mut self) -> Box<BlockStatement> {
+        let mut statements: Vec<Statement> = vec![];
         self.next();
 
         while self.curr_token != Token::Eof && self.curr_token != Token::RBrace {
@@ -219,7 +675,10 @@ impl Parser {
             self.next();
         }
 
-        Box::new(BlockStatement { stmts: statements })
+        Box::new(BlockStatement {
+            stmts: statements,
+            locals: Locals::default(),
+        })
     }
 
     pub fn parse_expression_statement(&mut self) -> Box<Statement> {
@@ -239,15 +698,24 @@ impl Parser {
         let t = self.curr_token.clone();
 
         let mut expr: Box<Expression> = match t {
+            Token::Identifiere(_) if self.peek() == Token::LBrace => self.parse_struct_literal(),
             Token::Identifiere(_s) => self.parse_identifier(),
             Token::Integer(_s) => self.parse_integer(),
             Token::String(_s) => self.parse_string(),
             Token::True | Token::False => self.parse_boolean(),
-            Token::Bang | Token::Minus => self.parse_prefix_expression(),
+            Token::Null => Box::new(Expression::Null),
+            Token::Bang | Token::Minus | Token::Tilde => self.parse_prefix_expression(),
+            Token::LParen if self.at_arrow_function() => self.parse_lambda(),
             Token::LParen => self.parse_group_expression(),
+            Token::Pipe => self.parse_lambda(),
             Token::If => self.parse_if_expression(),
+            Token::Try => self.parse_try_expression(),
+            Token::Match => self.parse_match_expression(),
             Token::Func => self.parse_function(),
             Token::LBracket => self.parse_array_literal(),
+            Token::LBrace if self.at_block_expression() => {
+                Box::new(Expression::Block(self.parse_block()))
+            }
             Token::LBrace => self.parse_hash_literal(),
             _ => panic!(
                 "Invalid token in expression {}, next token {}",
@@ -261,6 +729,7 @@ impl Parser {
             && self.peek_precedence() > precedence
         {
             let token = self.next();
+            let position = self.curr_position;
 
             expr = match token {
                 Token::Plus
@@ -270,7 +739,15 @@ impl Parser {
                 | Token::Eq
                 | Token::NotEq
                 | Token::Lt
-                | Token::Gt => {
+                | Token::Gt
+                | Token::Ampersand
+                | Token::Pipe
+                | Token::Caret
+                | Token::ShiftLeft
+                | Token::ShiftRight
+                | Token::NullCoalesce
+                | Token::DotDot
+                | Token::DotDotEq => {
                     self.next();
                     let infix = match token {
                         Token::Plus => Infix::Plus,
@@ -281,6 +758,14 @@ impl Parser {
                         Token::NotEq => Infix::NotEq,
                         Token::Lt => Infix::Lt,
                         Token::Gt => Infix::Gt,
+                        Token::Ampersand => Infix::BitAnd,
+                        Token::Pipe => Infix::BitOr,
+                        Token::Caret => Infix::BitXor,
+                        Token::ShiftLeft => Infix::ShiftLeft,
+                        Token::ShiftRight => Infix::ShiftRight,
+                        Token::NullCoalesce => Infix::NullCoalesce,
+                        Token::DotDot => Infix::Range,
+                        Token::DotDotEq => Infix::RangeInclusive,
                         _ => panic!("Invalid infix token {}", token),
                     };
 
@@ -288,10 +773,26 @@ impl Parser {
                         infix,
                         expr,
                         self.parse_expression(self.precedence(&token)),
+                        position,
                     ))
                 }
+                Token::Assign => self.parse_assignment(*expr, position),
+                Token::Question => self.parse_ternary(*expr, position),
+                Token::Pipeline => self.parse_pipeline(*expr, position),
                 Token::LParen => self.parse_function_call(expr),
                 Token::LBracket => self.parse_array_index(expr),
+                Token::QuestionBracket
+                    if self.starts_ternary_branch(
+                        std::iter::once(&self.next_token).chain(self.lexer.remaining()),
+                    ) =>
+                {
+                    // The `?` of a ternary and the `[` its first branch starts with
+                    self.curr_token = Token::LBracket;
+                    self.parse_ternary_branches(*expr, position)
+                }
+                Token::QuestionBracket => self.parse_optional_index(expr),
+                Token::QuestionDot => self.parse_optional_call(expr),
+                Token::Dot => self.parse_field_access(expr),
                 _ => expr,
             };
         }
@@ -299,13 +800,79 @@ impl Parser {
         expr
     }
 
-    pub fn parse_call_params(&mut self) -> Vec<Expression> {
-        let mut params: Vec<Expression> = vec![];
+    pub fn parse_assignment(&mut self, target: Expression, position: Position) -> Box<Expression> {
+        self.next();
+
+        // Assignment is right associative, so `a = b = 1` assigns both
+        let value = self.parse_expression(Precedence::Lowest);
+        match target {
+            Expression::Identifier(name, _) => Box::new(Expression::Assign(name, value, position)),
+            Expression::Index(container, idx, _) if !matches!(*idx, Expression::Slice(..)) => {
+                Box::new(Expression::IndexAssign(container, idx, value, position))
+            }
+            Expression::Field(object, field, _) => {
+                Box::new(Expression::FieldAssign(object, field, value, position))
+            }
+            target => panic!("Invalid assignment target {}", target),
+        }
+    }
+
+    ///
+    ///  Parses `value |> f`, which calls `f(value)`. When `f` is itself a
+    ///  call, like in `value |> f(a)`, the value becomes its first argument,
+    ///  making it `f(value, a)`
+    ///
+    fn parse_pipeline(&mut self, value: Expression, position: Position) -> Box<Expression> {
+        self.next();
+        let value = Argument::Positional(value);
+        match *self.parse_expression(Precedence::Pipeline) {
+            Expression::Call(func, mut args, position) => {
+                args.insert(0, value);
+                Box::new(Expression::Call(func, args, position))
+            }
+            Expression::OptionalCall(func, mut args, position) => {
+                args.insert(0, value);
+                Box::new(Expression::OptionalCall(func, args, position))
+            }
+            Expression::MethodCall(receiver, method, mut args, position) => {
+                args.insert(0, value);
+                Box::new(Expression::MethodCall(receiver, method, args, position))
+            }
+            func => Box::new(Expression::Call(Box::new(func), vec![value], position)),
+        }
+    }
+
+    pub fn parse_call_params(&mut self) -> Vec<Argument> {
+        let mut params: Vec<Argument> = vec![];
         self.expect_current_token(Token::LParen);
 
         while self.curr_token != Token::RParen {
-            let expr = self.parse_expression(Precedence::Lowest);
-            params.push(*expr);
+            let argument = match (&self.curr_token, self.peek()) {
+                (Token::Identifiere(name), Token::Colon) => {
+                    let name = Symbol::intern(name);
+                    if params
+                        .iter()
+                        .any(|p| matches!(p, Argument::Named(n, _) if *n == name))
+                    {
+                        panic!("Duplicate named argument {}", name);
+                    }
+                    self.next();
+                    self.next();
+                    Argument::Named(name, *self.parse_expression(Precedence::Lowest))
+                }
+                (Token::Ellipsis, _) => {
+                    self.next();
+                    Argument::Spread(*self.parse_expression(Precedence::Lowest))
+                }
+                _ => Argument::Positional(*self.parse_expression(Precedence::Lowest)),
+            };
+
+            if !matches!(argument, Argument::Named(..))
+                && params.iter().any(|p| matches!(p, Argument::Named(..)))
+            {
+                panic!("Positional argument {} follows named arguments", argument);
+            }
+            params.push(argument);
 
             if self.peek() == Token::Comma {
                 self.next();
@@ -318,15 +885,131 @@ impl Parser {
     }
 
     pub fn parse_function_call(&mut self, left: Box<Expression>) -> Box<Expression> {
+        let position = self.curr_position;
         let parameters = self.parse_call_params();
-        Box::new(Expression::Call(left, parameters))
+        Box::new(Expression::Call(left, parameters, position))
     }
 
     pub fn parse_array_index(&mut self, left: Box<Expression>) -> Box<Expression> {
+        let position = self.curr_position;
         self.expect_current_token(Token::LBracket);
-        let index_expr = self.parse_expression(Precedence::Lowest);
+        let index_expr = self.parse_index_or_slice();
+        Box::new(Expression::Index(left, index_expr, position))
+    }
+
+    pub fn parse_optional_index(&mut self, left: Box<Expression>) -> Box<Expression> {
+        let position = self.curr_position;
+        self.expect_current_token(Token::QuestionBracket);
+        let index_expr = self.parse_index_or_slice();
+        Box::new(Expression::OptionalIndex(left, index_expr, position))
+    }
+
+    ///
+    ///  Parses what is between the brackets of an index, either an index or
+    ///  a slice like `1:3`, `:-1` or `2:`, and consumes the closing bracket
+    ///
+    fn parse_index_or_slice(&mut self) -> Box<Expression> {
+        let start = match self.curr_token {
+            Token::Colon => None,
+            _ => {
+                let index = self.parse_before_colon();
+                if self.peek() != Token::Colon {
+                    self.expect_next_token(Token::RBracket);
+                    return index;
+                }
+                self.next();
+                Some(index)
+            }
+        };
+
+        let end = match self.peek() {
+            Token::RBracket => None,
+            _ => {
+                self.next();
+                Some(self.parse_expression(Precedence::Lowest))
+            }
+        };
         self.expect_next_token(Token::RBracket);
-        Box::new(Expression::Index(left, index_expr))
+        Box::new(Expression::Slice(start, end))
+    }
+
+    ///
+    ///  Parses `.field`, or `.method(args)` when the name is followed by a
+    ///  call
+    ///
+    pub fn parse_field_access(&mut self, left: Box<Expression>) -> Box<Expression> {
+        let position = self.curr_position;
+        let field = match self.next() {
+            Token::Identifiere(name) => Symbol::intern(&name),
+            token => panic!("Expected a field name after ., found {}", token),
+        };
+
+        if self.peek() == Token::LParen {
+            self.next();
+            let arguments = self.parse_call_params();
+            return Box::new(Expression::MethodCall(left, field, arguments, position));
+        }
+        Box::new(Expression::Field(left, field, position))
+    }
+
+    ///
+    ///  Parses `Point { x: 1, y }`, where a field without a value takes the
+    ///  variable of the same name
+    ///
+    fn parse_struct_literal(&mut self) -> Box<Expression> {
+        let position = self.curr_position;
+        let name = match &self.curr_token {
+            Token::Identifiere(name) => Variable::new(name),
+            token => panic!("Expected a struct name, found {}", token),
+        };
+        self.next();
+        self.expect_current_token(Token::LBrace);
+
+        let mut fields: Vec<(Symbol, Expression)> = vec![];
+        while self.curr_token != Token::RBrace {
+            let (field, value) = match self.curr_token.clone() {
+                Token::Identifiere(field) if self.peek() == Token::Colon => {
+                    self.next();
+                    self.next();
+                    (field, *self.parse_expression(Precedence::Lowest))
+                }
+                Token::Identifiere(field) => {
+                    let value = Expression::Identifier(Variable::new(&field), self.curr_position);
+                    (field, value)
+                }
+                token => panic!("Expected a field name in {} literal, found {}", name, token),
+            };
+
+            let field = Symbol::intern(&field);
+            if fields.iter().any(|(f, _)| *f == field) {
+                panic!("Duplicate field {} in {} literal", field, name);
+            }
+            fields.push((field, value));
+
+            if self.peek() != Token::RBrace {
+                self.expect_next_token(Token::Comma);
+            }
+            self.next();
+        }
+
+        Box::new(Expression::StructLiteral(name, fields, position))
+    }
+
+    ///
+    ///  Parses what follows a `?.`: the arguments of an optional call like
+    ///  `f?.(x)`, or the field of an optional field access like `a?.x`
+    ///
+    pub fn parse_optional_call(&mut self, left: Box<Expression>) -> Box<Expression> {
+        let position = self.curr_position;
+        if let Token::Identifiere(name) = self.peek() {
+            self.next();
+            let field = Symbol::intern(&name);
+            return Box::new(Expression::OptionalField(left, field, position));
+        }
+
+        self.expect_next_token(Token::LParen);
+        let parameters = self.parse_call_params();
+        Box::new(Expression::OptionalCall(left, parameters, position))
     }
 
     pub fn parse_array_literal(&mut self) -> Box<Expression> {
@@ -352,13 +1035,14 @@ impl Parser {
 
         self.expect_current_token(Token::LBrace);
         while self.curr_token != Token::RBrace {
-            let key = self.parse_expression(Precedence::Lowest);
+            let position = self.curr_position;
+            let key = self.parse_before_colon();
 
             self.expect_next_token(Token::Colon);
             self.next();
 
             let val = self.parse_expression(Precedence::Lowest);
-            key_values.push((*key, *val));
+            key_values.push((*key, *val, position));
 
             if self.peek() == Token::Comma {
                 self.next();
@@ -370,19 +1054,72 @@ impl Parser {
         Box::new(Expression::HashMapLiteral(key_values))
     }
 
-    pub fn parse_function_params(&mut self) -> Vec<String> {
-        let mut parameters: Vec<String> = vec![];
-
+    pub fn parse_function_params(&mut self) -> Vec<Parameter> {
         self.expect_current_token(Token::LParen);
+        self.parse_parameter_list(Token::RParen)
+    }
 
-        while self.curr_token != Token::RParen {
-            let idf = &self.curr_token;
+    ///
+    ///  Parses parameters up to the `end` token, leaving it the current
+    ///  token. Between `|`, a default value ends before any `|` of its own
+    ///
+    fn parse_parameter_list(&mut self, end: Token) -> Vec<Parameter> {
+        let mut parameters: Vec<Parameter> = vec![];
+        let default_precedence = match end {
+            Token::Pipe => Precedence::BitOr,
+            _ => Precedence::Lowest,
+        };
+
+        while self.curr_token != end {
+            if parameters.last().is_some_and(|p| p.variadic) {
+                panic!("Variadic parameter must be the last parameter");
+            }
+
+            let variadic = self.curr_token == Token::Ellipsis;
+            if variadic {
+                self.next();
+            }
 
-            let identifier = match idf {
-                Token::Identifiere(i) => i.to_string(),
-                _ => panic!("Unexpected function parameter {}", idf),
+            // A destructured parameter gets a hidden name from its position,
+            // so it has a slot of its own and no named argument can refer to it
+            let (name, pattern) = match &self.curr_token {
+                Token::Identifiere(i) => (Symbol::intern(i), None),
+                Token::LBracket | Token::LBrace | Token::LParen if !variadic => {
+                    let name = Symbol::intern(&format!("$param{}", parameters.len()));
+                    (name, Some(self.parse_pattern()))
+                }
+                idf => panic!("Unexpected function parameter {}", idf),
+            };
+            let names: Vec<Symbol> = match &pattern {
+                Some(pattern) => pattern.bindings().iter().map(|v| v.name).collect(),
+                None => vec![name],
             };
-            parameters.push(identifier);
+            for name in names {
+                if parameters.iter().any(|p| p.binds(name)) {
+                    panic!("Duplicate function parameter {}", name);
+                }
+            }
+
+            let default = if !variadic && self.peek() == Token::Assign {
+                self.next();
+                self.next();
+                Some(*self.parse_expression(default_precedence))
+            } else {
+                None
+            };
+            if default.is_none() && !variadic && parameters.iter().any(|p| p.default.is_some()) {
+                panic!(
+                    "Parameter {} without default follows default parameters",
+                    name
+                );
+            }
+
+            parameters.push(Parameter {
+                name,
+                pattern,
+                default,
+                variadic,
+            });
 
             if self.peek() == Token::Comma {
                 self.next();
@@ -400,13 +1137,266 @@ impl Parser {
         let parameters = self.parse_function_params();
         let body = self.parse_block_statement();
 
-        Box::new(Expression::FunctionLiteral(parameters, body))
+        Box::new(Expression::FunctionLiteral(Rc::new(Function {
+            params: parameters,
+            body: *body,
+            locals: Locals::default(),
+        })))
+    }
+
+    ///
+    ///  Whether the `(` at the current token opens the parameters of an
+    ///  arrow function, `(a, b) => a + b`, rather than a group or a tuple
+    ///
+    fn at_arrow_function(&self) -> bool {
+        let mut depth = 0;
+        let mut tokens = [&self.curr_token, &self.next_token]
+            .into_iter()
+            .chain(self.lexer.remaining());
+        let closed = tokens.by_ref().any(|token| {
+            match token {
+                Token::LParen => depth += 1,
+                Token::RParen => depth -= 1,
+                _ => {}
+            }
+            depth == 0
+        });
+        closed && tokens.next() == Some(&Token::FatArrow)
+    }
+
+    ///
+    ///  Whether the `{` at the current token opens a block rather than a
+    ///  hashmap literal. `{}` is an empty hashmap, and a hashmap has a `:`
+    ///  after its first key, before anything ends a statement
+    ///
+    fn at_block_expression(&self) -> bool {
+        if self.next_token == Token::RBrace {
+            return false;
+        }
+
+        // Each `?` of a ternary has a `:` of its own
+        let mut ternaries = 0;
+        let mut depth = 0;
+        let tokens = [&self.curr_token, &self.next_token]
+            .into_iter()
+            .chain(self.lexer.remaining());
+        for token in tokens {
+            match token {
+                Token::LParen | Token::LBracket | Token::QuestionBracket | Token::LBrace => {
+                    depth += 1
+                }
+                Token::RParen | Token::RBracket | Token::RBrace => depth -= 1,
+                Token::Question if depth == 1 => ternaries += 1,
+                Token::Colon if depth == 1 && ternaries > 0 => ternaries -= 1,
+                Token::Colon if depth == 1 => return false,
+                Token::Semicolon if depth == 1 => return true,
+                _ => {}
+            }
+            if depth == 0 {
+                break;
+            }
+        }
+        true
+    }
+
+    ///
+    ///  Parses the shorthand functions `|a, b| a + b` and `(a, b) => a + b`,
+    ///  whose body is a single expression. They take the same parameters as
+    ///  `fn`, and are plain function literals once parsed
+    ///
+    fn parse_lambda(&mut self) -> Box<Expression> {
+        let params = match self.curr_token {
+            Token::Pipe => {
+                self.expect_current_token(Token::Pipe);
+                self.parse_parameter_list(Token::Pipe)
+            }
+            _ => {
+                let params = self.parse_function_params();
+                self.expect_next_token(Token::FatArrow);
+                params
+            }
+        };
+        self.next();
+        let body = self.parse_expression(Precedence::Lowest);
+
+        Box::new(Expression::FunctionLiteral(Rc::new(Function {
+            params,
+            body: BlockStatement {
+                stmts: vec![Statement::Expression(body)],
+                locals: Locals::default(),
+            },
+            locals: Locals::default(),
+        })))
+    }
+
+    pub fn parse_function_declaration(&mut self) -> Box<Statement> {
+        let name = match self.next() {
+            Token::Identifiere(s) => s,
+            token => panic!("Expected function name, found {}", token),
+        };
+        self.next();
+
+        let parameters = self.parse_function_params();
+        let body = self.parse_block_statement();
+
+        if self.peek() == Token::Semicolon {
+            self.next();
+        }
+
+        Box::new(Statement::Function(
+            Variable::new(&name),
+            Rc::new(Function {
+                params: parameters,
+                body: *body,
+                locals: Locals::default(),
+            }),
+        ))
+    }
+
+    fn parse_struct_declaration(&mut self) -> Box<Statement> {
+        let name = match self.next() {
+            Token::Identifiere(name) => name,
+            token => panic!("Expected struct name, found {}", token),
+        };
+        self.expect_next_token(Token::LBrace);
+        self.next();
+
+        let mut fields: Vec<Symbol> = vec![];
+        let mut methods: Vec<(Symbol, Rc<Function>)> = vec![];
+        while self.curr_token != Token::RBrace {
+            if self.curr_token == Token::Func {
+                let method = match self.next() {
+                    Token::Identifiere(method) => Symbol::intern(&method),
+                    token => panic!("Expected a method name in struct {}, found {}", name, token),
+                };
+                if fields.contains(&method) || methods.iter().any(|(m, _)| *m == method) {
+                    panic!("Duplicate method {} in struct {}", method, name);
+                }
+                self.next();
+
+                let params = self.parse_function_params();
+                let body = self.parse_block_statement();
+                methods.push((
+                    method,
+                    Rc::new(Function {
+                        params,
+                        body: *body,
+                        locals: Locals::default(),
+                    }),
+                ));
+
+                // Methods may be separated by commas, like fields
+                if self.peek() == Token::Comma {
+                    self.next();
+                }
+                self.next();
+                continue;
+            }
+
+            let field = match &self.curr_token {
+                Token::Identifiere(field) => Symbol::intern(field),
+                token => panic!("Expected a field name in struct {}, found {}", name, token),
+            };
+            if fields.contains(&field) || methods.iter().any(|(m, _)| *m == field) {
+                panic!("Duplicate field {} in struct {}", field, name);
+            }
+            fields.push(field);
+
+            if self.peek() != Token::RBrace {
+                self.expect_next_token(Token::Comma);
+            }
+            self.next();
+        }
+
+        if self.peek() == Token::Semicolon {
+            self.next();
+        }
+
+        Box::new(Statement::Struct(
+            Variable::new(&name),
+            Rc::new(StructDef {
+                name: Symbol::intern(&name),
+                fields,
+                methods,
+            }),
+        ))
+    }
+
+    ///
+    ///  Parses `enum Shape { Circle(r), Rect(w, h), Empty }`
+    ///
+    fn parse_enum_declaration(&mut self) -> Box<Statement> {
+        let name = match self.next() {
+            Token::Identifiere(name) => name,
+            token => panic!("Expected enum name, found {}", token),
+        };
+        self.expect_next_token(Token::LBrace);
+        self.next();
+
+        let mut variants: Vec<(Symbol, Vec<Symbol>)> = vec![];
+        while self.curr_token != Token::RBrace {
+            let variant = match &self.curr_token {
+                Token::Identifiere(variant) => Symbol::intern(variant),
+                token => panic!("Expected a variant name in enum {}, found {}", name, token),
+            };
+            if variants.iter().any(|(v, _)| *v == variant) {
+                panic!("Duplicate variant {} in enum {}", variant, name);
+            }
+
+            let mut fields: Vec<Symbol> = vec![];
+            if self.peek() == Token::LParen {
+                self.next();
+                self.next();
+                while self.curr_token != Token::RParen {
+                    let field = match &self.curr_token {
+                        Token::Identifiere(field) => Symbol::intern(field),
+                        token => panic!(
+                            "Expected a field name in {}.{}, found {}",
+                            name, variant, token
+                        ),
+                    };
+                    if fields.contains(&field) {
+                        panic!("Duplicate field {} in {}.{}", field, name, variant);
+                    }
+                    fields.push(field);
+
+                    if self.peek() != Token::RParen {
+                        self.expect_next_token(Token::Comma);
+                    }
+                    self.next();
+                }
+            }
+            variants.push((variant, fields));
+
+            if self.peek() != Token::RBrace {
+                self.expect_next_token(Token::Comma);
+            }
+            self.next();
+        }
+
+        if self.peek() == Token::Semicolon {
+            self.next();
+        }
+
+        Box::new(Statement::Enum(
+            Variable::new(&name),
+            Rc::new(EnumDef {
+                name: Symbol::intern(&name),
+                variants,
+            }),
+        ))
     }
 
     pub fn parse_statement(&mut self) -> Box<Statement> {
         match self.curr_token {
-            Token::Let => self.parse_let_statement(),
+            Token::Let | Token::Const => self.parse_let_statement(),
+            Token::Func if matches!(self.peek(), Token::Identifiere(_)) => {
+                self.parse_function_declaration()
+            }
+            Token::Struct => self.parse_struct_declaration(),
+            Token::Enum => self.parse_enum_declaration(),
             Token::Ret => self.parse_return_statement(),
+            Token::Throw => self.parse_throw_statement(),
             _ => self.parse_expression_statement(),
         }
     }
@@ -426,7 +1416,7 @@ impl Parser {
 
 #[cfg(test)]
 mod tests {
-    use crate::ast::Expression;
+    use crate::ast::{join, Expression};
     use crate::ast::{Prefix, Statement};
     use crate::lexer::{Lexer, Token};
     use crate::parser::Parser;
@@ -457,7 +1447,7 @@ mod tests {
 
     #[test]
     fn test_parser() {
-        let lexer = Lexer::new(TEST_STR);
+        let lexer = Lexer::new(TEST_STR).unwrap();
         let mut parser = Parser::new(lexer);
 
         let mut token = parser.next();
@@ -469,7 +1459,7 @@ mod tests {
     }
 
     fn test_case_statements(input: &str) -> Vec<Statement> {
-        let lexer = Lexer::new(input);
+        let lexer = Lexer::new(input).unwrap();
         let mut parser = Parser::new(lexer);
         let program = parser.parse_program().unwrap();
         program.stmts
@@ -486,12 +1476,11 @@ mod tests {
         let statements = test_case_statements(TEST_RETURN_STATEMENTS_STR);
         assert_eq!(statements.len(), 3);
 
-        let mut idx = 0;
-        for stmt in statements.iter() {
-            let _ret_stmt = match stmt {
+        for (idx, stmt) in statements.iter().enumerate() {
+            match stmt {
                 Statement::Return(expr) => match idx {
                     0 => {
-                        assert_eq!(expr.is_none(), true);
+                        assert!(expr.is_none());
                         assert_eq!(stmt.to_string(), "return;")
                     }
                     1 => assert_eq!(stmt.to_string(), "return 5;"),
@@ -500,8 +1489,6 @@ mod tests {
                 },
                 _ => panic!("{}: Expected return statement but found {}", idx, stmt),
             };
-
-            idx += 1;
         }
     }
 
@@ -515,11 +1502,10 @@ mod tests {
         let statements = test_case_statements(TEST_PREFIX_STR);
         assert_eq!(statements.len(), 2);
 
-        let mut idx = 0;
-        for stmt in statements.iter() {
-            let _prefix_expr = match stmt {
+        for (idx, stmt) in statements.iter().enumerate() {
+            match stmt {
                 Statement::Expression(expr) => match &**expr {
-                    Expression::Prefix(prefix, expr2) => match idx {
+                    Expression::Prefix(prefix, expr2, _) => match idx {
                         0 => {
                             assert_eq!(*prefix, Prefix::Bang);
                             assert_eq!(expr2.to_string(), "y");
@@ -537,10 +1523,470 @@ mod tests {
                     stmt
                 ),
             };
-            idx += 1;
         }
     }
 
+    const TEST_BITWISE_PRECEDENCE_STR: &str = "
+        a | b ^ c & d;
+        1 << 2 + 3;
+        x & 1 == 0;
+        ~a & -b;
+    ";
+
+    #[test]
+    fn test_parser_bitwise_precedence() {
+        let statements = test_case_statements(TEST_BITWISE_PRECEDENCE_STR);
+        let expected = [
+            "(| a (^ b (& c d)));",
+            "(<< 1 (+ 2 3));",
+            "(== (& x 1) 0);",
+            "(& (~, a) (-, b));",
+        ];
+
+        assert_eq!(statements.len(), expected.len());
+        for (stmt, expected) in statements.iter().zip(expected) {
+            assert_eq!(stmt.to_string(), expected);
+        }
+    }
+
+    const TEST_NULL_OPERATORS_STR: &str = "
+        a ?? b == null;
+        a?[1]?[\"k\"] ?? 0;
+        f?.(x, y) ?? g(1);
+        a?.b?.c ?? d.e;
+    ";
+
+    #[test]
+    fn test_parser_null_operators() {
+        let statements = test_case_statements(TEST_NULL_OPERATORS_STR);
+        let expected = [
+            "(?? a (== b null));",
+            "(?? a?[1]?[\"k\"] 0);",
+            "(?? f?.(x,y) g(1));",
+            "(?? a?.b?.c d.e);",
+        ];
+
+        assert_eq!(statements.len(), expected.len());
+        for (stmt, expected) in statements.iter().zip(expected) {
+            assert_eq!(stmt.to_string(), expected);
+        }
+    }
+
+    const TEST_FUNCTION_ARGUMENTS_STR: &str = "
+        fn(a, b = 10, ...rest) { a; };
+        fn greet(name, greeting = \"hi\" + \"!\") { greeting; }
+        f(1, ...xs, ...[2, 3]);
+        f(1, b: 2 + 3, c: g(x: 1));
+        f?.(...xs, a: 1);
+    ";
+
+    #[test]
+    fn test_parser_function_arguments() {
+        let statements = test_case_statements(TEST_FUNCTION_ARGUMENTS_STR);
+        let expected = [
+            "fn(a,b=10,...rest){a;};",
+            "fn greet(name,greeting=(+ \"hi\" \"!\")){greeting;}",
+            "f(1,...xs,...[2,3]);",
+            "f(1,b: (+ 2 3),c: g(x: 1));",
+            "f?.(...xs,a: 1);",
+        ];
+
+        assert_eq!(statements.len(), expected.len());
+        for (stmt, expected) in statements.iter().zip(expected) {
+            assert_eq!(stmt.to_string(), expected);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "Variadic parameter must be the last parameter")]
+    fn test_parser_variadic_not_last() {
+        test_case_statements("fn(...rest, a) { a; };");
+    }
+
+    #[test]
+    #[should_panic(expected = "Positional argument 2 follows named arguments")]
+    fn test_parser_positional_after_named() {
+        test_case_statements("f(a: 1, 2);");
+    }
+
+    const TEST_ASSIGN_STR: &str = "
+        x = 5;
+        x = y = x + 1;
+        f(a = 2);
+        a[i + 1] = b[i];
+        m[\"k\"][0] = x = 1;
+    ";
+
+    #[test]
+    fn test_parser_assignment() {
+        let statements = test_case_statements(TEST_ASSIGN_STR);
+        let expected = [
+            "(= x 5);",
+            "(= x (= y (+ x 1)));",
+            "f((= a 2));",
+            "(= a[(+ i 1)] b[i]);",
+            "(= m[\"k\"][0] (= x 1));",
+        ];
+
+        assert_eq!(statements.len(), expected.len());
+        for (stmt, expected) in statements.iter().zip(expected) {
+            assert_eq!(stmt.to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn test_parser_slices_and_ranges() {
+        let statements = test_case_statements(
+            "a[1:3]; a[:-1]; s[2:]; a[:]; a?[i:]; 0..n + 1; 1..=10; x ?? 0..2;",
+        );
+        let expected = [
+            "a[1:3];",
+            "a[:(-, 1)];",
+            "s[2:];",
+            "a[:];",
+            "a?[i:];",
+            "(.. 0 (+ n 1));",
+            "(..= 1 10);",
+            "(?? x (.. 0 2));",
+        ];
+
+        assert_eq!(statements.len(), expected.len());
+        for (stmt, expected) in statements.iter().zip(expected) {
+            assert_eq!(stmt.to_string(), expected);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid assignment target")]
+    fn test_parser_slice_assignment() {
+        test_case_statements("a[1:2] = [3];");
+    }
+
+    #[test]
+    fn test_parser_structs() {
+        let statements = test_case_statements(
+            "struct Point { x, y, }
+             struct Empty {}
+             let p = Point { x: 1 + 2, y };
+             p.x.y[0];
+             p.x = q.y = 3;
+             f(a).b.c(d);",
+        );
+        let expected = [
+            "struct Point { x,y };",
+            "struct Empty {  };",
+            "let p = Point { x: (+ 1 2),y: y };",
+            "p.x.y[0];",
+            "(= p.x (= q.y 3));",
+            "f(a).b.c(d);",
+        ];
+
+        assert_eq!(statements.len(), expected.len());
+        for (stmt, expected) in statements.iter().zip(expected) {
+            assert_eq!(stmt.to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn test_parser_methods() {
+        let statements = test_case_statements(
+            "struct Counter { n, fn inc(self, by) { self.n = self.n + by; }, fn get(self) { self.n } }
+             arr.push(4).len();
+             s.upper()[0];",
+        );
+        let expected = [
+            "struct Counter { n,fn inc(self,by){(= self.n (+ self.n by));},fn get(self){self.n;} };",
+            "arr.push(4).len();",
+            "s.upper()[0];",
+        ];
+
+        assert_eq!(statements.len(), expected.len());
+        for (stmt, expected) in statements.iter().zip(expected) {
+            assert_eq!(stmt.to_string(), expected);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "Duplicate method get in struct Counter")]
+    fn test_parser_duplicate_struct_method() {
+        test_case_statements("struct Counter { fn get(self) {} fn get(self) {} }");
+    }
+
+    #[test]
+    fn test_parser_match() {
+        let statements = test_case_statements(
+            "enum Shape { Circle(r), Rect(w, h,), Empty, }
+             match (s) {
+                 Shape.Circle(r) if r > 1 => r * r,
+                 Shape.Empty => 0,
+                 [first, ...rest] => { first }
+                 [_, ...] => 1,
+                 (a, -1, (b,)) => a,
+                 {name, \"age\": 30, 1: true} => name,
+                 Point { x, y: null } => x,
+                 _ => \"other\"
+             };
+             match (x) {};",
+        );
+        let expected = [
+            "enum Shape { Circle(r),Rect(w,h),Empty };",
+            "match (s) { Shape.Circle(r) if (> r 1) => {(* r r);},\
+             Shape.Empty => {0;},\
+             [first,...rest] => {first;},\
+             [_,..._] => {1;},\
+             (a,-1,(b,)) => {a;},\
+             {\"name\":name,\"age\":30,1:true} => {name;},\
+             Point { x: x,y: null } => {x;},\
+             _ => {\"other\";} };",
+            "match (x) {  };",
+        ];
+
+        assert_eq!(statements.len(), expected.len());
+        for (stmt, expected) in statements.iter().zip(expected) {
+            assert_eq!(stmt.to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn test_parser_destructuring() {
+        let statements = test_case_statements(
+            "let [a, b, ...rest] = arr;
+             let {name, \"age\": years} = person;
+             let (x, _) = pair;
+             const Point { x: px } = p;
+             fn f([a, b], {c}, d = [1], ...e) { a }
+             let g = fn((a, b) = (1, 2)) { a };",
+        );
+        let expected = [
+            "let [a,b,...rest] = arr;",
+            "let {\"name\":name,\"age\":years} = person;",
+            "let (x,_) = pair;",
+            "const Point { x: px } = p;",
+            "fn f([a,b],{\"c\":c},d=[1],...e){a;}",
+            "let g = fn((a,b)=(1,2)){a;};",
+        ];
+
+        assert_eq!(statements.len(), expected.len());
+        for (stmt, expected) in statements.iter().zip(expected) {
+            assert_eq!(stmt.to_string(), expected);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "Duplicate function parameter a")]
+    fn test_parser_duplicate_destructured_parameter() {
+        test_case_statements("fn f(a, [b, a]) { a }");
+    }
+
+    #[test]
+    fn test_parser_pipelines() {
+        let statements = test_case_statements(
+            "a |> f;
+             a |> f(b) |> g;
+             a + 1 |> f >> g;
+             x = a ?? b |> f;
+             a |> p.m(b) |> f?.(c);",
+        );
+        let expected = [
+            "f(a);",
+            "g(f(a,b));",
+            "(>> f g)((+ a 1));",
+            "(= x f((?? a b)));",
+            "f?.(p.m(a,b),c);",
+        ];
+
+        assert_eq!(statements.len(), expected.len());
+        for (stmt, expected) in statements.iter().zip(expected) {
+            assert_eq!(stmt.to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn test_parser_block_expressions() {
+        let statements = test_case_statements(
+            "let v = { let t = f(); t * 2 };
+             { x };
+             {};
+             { \"a\": 1 };
+             { (1, 2): [a[1:2]] };
+             { f(a: 1); { b } };",
+        );
+        let expected = [
+            "let v = {let t = f();(* t 2);};",
+            "{x;};",
+            "{};",
+            "{\"a\":1};",
+            "{(1,2):[a[1:2]]};",
+            "{f(a: 1);{b;};};",
+        ];
+
+        assert_eq!(statements.len(), expected.len());
+        for (stmt, expected) in statements.iter().zip(expected) {
+            assert_eq!(stmt.to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn test_parser_else_if_and_ternary() {
+        let statements = test_case_statements(
+            "if (a) { 1 } else if (b) { 2 } else if (c) { 3 } else { 4 }
+             if (a) { 1 } else if (b) { 2 }
+             fn f(c) { if (c) { 1 } else { 2 } }
+             x = a ? b : c;
+             a ? b : c ? d : e;
+             a > 1 ? b + 1 : c |> f;
+             { a ? b : c };
+             { a ? b : c: d };
+             f(a ? [1] : [2], k: b ? 1 : 2);
+             c ?[1] : [2];
+             a + c ?[1][0] : x?[1];
+             a?[1] ? b : c;
+             x ? a?[1] : b;
+             x ? c ?[1] : [2] : [3];
+             {a?[1]: 2, c ?[1] : [2]: 3};
+             m[a?[0]:2];
+             f(c ?[1] : [2], k: a?[1]);",
+        );
+        let expected = [
+            "if (a) {1;} else if (b) {2;} else if (c) {3;} else {4;};",
+            "if (a) {1;} else if (b) {2;};",
+            "fn f(c){if (c) {1;} else {2;};}",
+            "(= x (? a b c));",
+            "(? a b (? c d e));",
+            "(? (> a 1) (+ b 1) f(c));",
+            "{(? a b c);};",
+            "{(? a b c):d};",
+            "f((? a [1] [2]),k: (? b 1 2));",
+            "(? c [1] [2]);",
+            "(? (+ a c) [1][0] x?[1]);",
+            "(? a?[1] b c);",
+            "(? x a?[1] b);",
+            "(? x (? c [1] [2]) [3]);",
+            "{a?[1]:2,(? c [1] [2]):3};",
+            "m[a?[0]:2];",
+            "f((? c [1] [2]),k: a?[1]);",
+        ];
+
+        assert_eq!(statements.len(), expected.len());
+        for (stmt, expected) in statements.iter().zip(expected) {
+            assert_eq!(stmt.to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn test_parser_lambdas() {
+        let statements = test_case_statements(
+            "|x| x * 2;
+             (x) => x * 2;
+             || 1;
+             () => 1;
+             |a, [b, c], d = (1 | 2)| a;
+             (a, b = 1) => |c| a + c;
+             (a);
+             (a, b) + (1);",
+        );
+        let expected = [
+            "fn(x){(* x 2);};",
+            "fn(x){(* x 2);};",
+            "fn(){1;};",
+            "fn(){1;};",
+            "fn(a,[b,c],d=(| 1 2)){a;};",
+            "fn(a,b=1){fn(c){(+ a c);};};",
+            "a;",
+            "(+ (a,b) 1);",
+        ];
+
+        assert_eq!(statements.len(), expected.len());
+        for (stmt, expected) in statements.iter().zip(expected) {
+            assert_eq!(stmt.to_string(), expected);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "Duplicate binding a in pattern [a,a]")]
+    fn test_parser_duplicate_pattern_binding() {
+        test_case_statements("match (x) { [a, a] => a }");
+    }
+
+    #[test]
+    #[should_panic(expected = "Duplicate variant Circle in enum Shape")]
+    fn test_parser_duplicate_enum_variant() {
+        test_case_statements("enum Shape { Circle(r), Circle }");
+    }
+
+    #[test]
+    #[should_panic(expected = "Duplicate field x in struct Point")]
+    fn test_parser_duplicate_struct_field() {
+        test_case_statements("struct Point { x, x }");
+    }
+
+    #[test]
+    #[should_panic(expected = "Duplicate field x in Point literal")]
+    fn test_parser_duplicate_struct_literal_field() {
+        test_case_statements("Point { x: 1, x: 2 }");
+    }
+
+    #[test]
+    fn test_parser_tuple_literal() {
+        let statements = test_case_statements("(); (1,); (1, 2); (1, 2,); ((1 + 2), 3); (1);");
+        let expected = ["();", "(1,);", "(1,2);", "(1,2);", "((+ 1 2),3);", "1;"];
+
+        assert_eq!(statements.len(), expected.len());
+        for (stmt, expected) in statements.iter().zip(expected) {
+            assert_eq!(stmt.to_string(), expected);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid assignment target")]
+    fn test_parser_invalid_assignment_target() {
+        test_case_statements("1 = 2;");
+    }
+
+    const TEST_TRY_STR: &str = "
+        try { f(x); } catch (e) { e; };
+        try { f(x); } finally { g(); };
+        try { throw \"boom\"; } catch (err) { 1 } finally { 2 }
+    ";
+
+    #[test]
+    fn test_parser_try_throw() {
+        let statements = test_case_statements(TEST_TRY_STR);
+        let expected = [
+            "try {f(x);} catch (e) {e;};",
+            "try {f(x);} finally {g();};",
+            "try {throw \"boom\";} catch (err) {1;} finally {2;};",
+        ];
+
+        assert_eq!(statements.len(), expected.len());
+        for (stmt, expected) in statements.iter().zip(expected) {
+            assert_eq!(stmt.to_string(), expected);
+        }
+    }
+
+    const TEST_FUNCTION_DECLARATION_STR: &str = "
+        fn add(a, b) { a + b; }
+        fn noop() {};
+        fn(x) { x; }(1);
+    ";
+
+    #[test]
+    fn test_parser_function_declaration() {
+        let statements = test_case_statements(TEST_FUNCTION_DECLARATION_STR);
+        assert_eq!(statements.len(), 3);
+
+        match &statements[0] {
+            Statement::Function(name, function) => {
+                assert_eq!(name.to_string(), "add");
+                assert_eq!(join(&function.params), "a,b");
+                assert_eq!(function.body.to_string(), "{(+ a b);}");
+            }
+            stmt => panic!("Expected function declaration, found {}", stmt),
+        }
+        assert_eq!(statements[1].to_string(), "fn noop(){}");
+        assert_eq!(statements[2].to_string(), "fn(x){x;}(1);");
+    }
+
     const TEST_IF_NO_ELSE_STR: &str = "
         if (x > y) {
             let z = x + y;
@@ -555,10 +2001,11 @@ mod tests {
         let stmt = &statements[0];
         match stmt {
             Statement::Expression(expr) => match &**expr {
-                Expression::If(cond, true_block, false_block) => {
+                Expression::If(branches, false_block) => {
+                    let (cond, _, true_block) = &branches[0];
                     assert_eq!(cond.to_string(), "(> x y)");
                     assert_eq!(true_block.to_string(), "{let z = (+ x y);}");
-                    assert_eq!(false_block.is_none(), true);
+                    assert!(false_block.is_none());
                 }
                 _ => panic!("Expected if expression"),
             },
@@ -584,7 +2031,8 @@ mod tests {
         let stmt = &statements[0];
         match stmt {
             Statement::Expression(expr) => match &**expr {
-                Expression::If(cond, true_block, false_block) => {
+                Expression::If(branches, false_block) => {
+                    let (cond, _, true_block) = &branches[0];
                     assert_eq!(cond.to_string(), "(> x y)");
                     assert_eq!(true_block.to_string(), "{(+ (* x 2) 3);let x = y;}");
                     assert_eq!(
@@ -619,10 +2067,10 @@ mod tests {
         let stmt = &statements[0];
         match stmt {
             Statement::Expression(expr) => match &**expr {
-                Expression::FunctionLiteral(params, block) => {
-                    assert_eq!(params.iter().as_ref().join(","), "x,y,z");
-                    assert_eq!(block.stmts[0].to_string(), "let z = (+ x y);");
-                    assert_eq!(block.stmts[1].to_string(), "z;");
+                Expression::FunctionLiteral(function) => {
+                    assert_eq!(join(&function.params), "x,y,z");
+                    assert_eq!(function.body.stmts[0].to_string(), "let z = (+ x y);");
+                    assert_eq!(function.body.stmts[1].to_string(), "z;");
                 }
                 _ => panic!("Expected function literal"),
             },
@@ -643,9 +2091,9 @@ mod tests {
         let stmt = &statements[0];
         match stmt {
             Statement::Expression(expr) => match &**expr {
-                Expression::FunctionLiteral(params, block) => {
-                    assert_eq!(params.len(), 0);
-                    assert_eq!(block.stmts[0].to_string(), "(* 10 20);");
+                Expression::FunctionLiteral(function) => {
+                    assert_eq!(function.params.len(), 0);
+                    assert_eq!(function.body.stmts[0].to_string(), "(* 10 20);");
                 }
                 _ => panic!("Expected function literal"),
             },
@@ -684,10 +2132,10 @@ mod tests {
         let statements = test_case_statements(TEST_FUNCTION_CALL_STR);
         assert_eq!(statements.len(), 4);
 
-        for idx in 0..statements.len() {
-            match &statements[idx] {
+        for (idx, stmt) in statements.iter().enumerate() {
+            match stmt {
                 Statement::Expression(expr) => match &**expr {
-                    Expression::Call(func_expr, params) => match idx {
+                    Expression::Call(func_expr, params, _) => match idx {
                         0 => {
                             assert_eq!(func_expr.to_string(), "sum");
                             assert_eq!(params.len(), 0);
@@ -746,7 +2194,7 @@ mod tests {
         for stmt in statements.iter() {
             match stmt {
                 Statement::Expression(expr) => match &**expr {
-                    Expression::String(s) => println!("{}", s.to_string()),
+                    Expression::String(s) => println!("{}", s),
                     _ => panic!("Expected string literal in expression found {}", expr),
                 },
                 _ => panic!("Expected a string expression"),
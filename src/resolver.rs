@@ -0,0 +1,771 @@
+use crate::ast::*;
+use crate::evaluator::{immutable_assignment, RuntimeError, NAME_ERROR, TYPE_ERROR};
+use crate::lexer::Position;
+use crate::symbol::Symbol;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+type ResolveResult = Result<(), RuntimeError>;
+
+///
+///  A local scope: the parameters and variables of a function call, the
+///  error and variables of a catch clause, the bindings and variables of a
+///  match arm, or the variables of a block
+///
+struct Scope {
+    slots: HashMap<Symbol, usize>,
+    names: Vec<Symbol>,
+    defined: HashSet<Symbol>,
+    constants: HashSet<Symbol>,
+    /// The slots of the variables a plain `let` declares
+    immutables: HashSet<usize>,
+    function: bool,
+}
+
+impl Scope {
+    fn new(function: bool) -> Self {
+        Scope {
+            slots: HashMap::new(),
+            names: vec![],
+            defined: HashSet::new(),
+            constants: HashSet::new(),
+            immutables: HashSet::new(),
+            function,
+        }
+    }
+
+    fn declare(&mut self, name: Symbol) {
+        if !self.slots.contains_key(&name) {
+            self.slots.insert(name, self.names.len());
+            self.names.push(name);
+        }
+    }
+
+    ///
+    ///  Gives a variable of a `let` or `const` statement a slot of its own,
+    ///  so that each declaration of a name keeps its own mutability. The name
+    ///  refers to the first of them until a later one is defined
+    ///
+    fn declare_binding(&mut self, name: Symbol) -> usize {
+        let slot = self.names.len();
+        self.slots.entry(name).or_insert(slot);
+        self.names.push(name);
+        slot
+    }
+}
+
+///
+///  Resolves every variable of a program to a slot in an enclosing local
+///  scope, or to a global, before it runs. A variable used before the
+///  statement defining it in the same function is reported here, while a
+///  closure may use one defined after it, as it only runs later
+///
+pub struct Resolver {
+    scopes: Vec<Scope>,
+    /// The variables the program declares at its top level
+    globals: HashSet<Symbol>,
+    warnings: Vec<Warning>,
+}
+
+///
+///  Valid code that is likely a mistake, like a `let` hiding a variable of
+///  an enclosing scope
+///
+#[derive(Debug, PartialEq)]
+pub struct Warning {
+    pub message: String,
+    pub position: Position,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "warning: {} at {}", self.message, self.position)
+    }
+}
+
+pub fn resolve_program(program: &Program) -> Result<Vec<Warning>, RuntimeError> {
+    let mut globals = HashSet::new();
+    for stmt in &program.stmts {
+        match stmt {
+            Statement::Let(pattern, ..)
+            | Statement::LetMut(pattern, ..)
+            | Statement::Const(pattern, ..) => {
+                globals.extend(pattern.bindings().iter().map(|v| v.name))
+            }
+            Statement::Function(variable, _)
+            | Statement::Struct(variable, _)
+            | Statement::Enum(variable, _) => {
+                globals.insert(variable.name);
+            }
+            _ => {}
+        }
+    }
+
+    let mut resolver = Resolver {
+        scopes: vec![],
+        globals,
+        warnings: vec![],
+    };
+    resolver.resolve_statements(&program.stmts)?;
+    Ok(resolver.warnings)
+}
+
+impl Resolver {
+    fn lookup(&self, variable: &Variable, position: Position) -> ResolveResult {
+        let mut crossed_function = false;
+        for (depth, scope) in self.scopes.iter().rev().enumerate() {
+            if let Some(slot) = scope.slots.get(&variable.name) {
+                if !crossed_function && !scope.defined.contains(&variable.name) {
+                    return Err(RuntimeError::new(
+                        NAME_ERROR,
+                        format!("variable `{}` used before its definition", variable),
+                    )
+                    .at(position));
+                }
+
+                let slot = *slot;
+                variable.binding.set(Binding::Local { depth, slot });
+                return Ok(());
+            }
+            crossed_function |= scope.function;
+        }
+
+        variable.binding.set(Binding::Global);
+        Ok(())
+    }
+
+    ///
+    ///  Defines a variable of a `let` or `const` statement. A variable some
+    ///  `const` of a scope declares is a constant throughout that scope, so
+    ///  no other statement may define it there
+    ///
+    fn define_binding(
+        &mut self,
+        variable: &Variable,
+        constant: bool,
+        position: Position,
+    ) -> ResolveResult {
+        if let Some((scope, outer)) = self.scopes.split_last() {
+            let name = variable.name;
+            if scope.constants.contains(&name) && (!constant || scope.defined.contains(&name)) {
+                return Err(RuntimeError::new(
+                    TYPE_ERROR,
+                    format!("cannot redeclare constant `{}`", variable),
+                )
+                .at(position));
+            }
+
+            let shadows =
+                outer.iter().any(|s| s.slots.contains_key(&name)) || self.globals.contains(&name);
+            if shadows && !scope.defined.contains(&name) {
+                self.warnings.push(Warning {
+                    message: format!("`{}` shadows a variable of an enclosing scope", variable),
+                    position,
+                });
+            }
+        }
+
+        match self.scopes.last_mut() {
+            Some(scope) => {
+                let Binding::Local { slot, .. } = variable.binding.get() else {
+                    unreachable!("`{}` was declared in this scope", variable)
+                };
+                scope.slots.insert(variable.name, slot);
+                scope.defined.insert(variable.name);
+            }
+            None => variable.binding.set(Binding::Global),
+        }
+        Ok(())
+    }
+
+    fn define(&mut self, variable: &Variable) {
+        let binding = match self.scopes.last_mut() {
+            Some(scope) => {
+                scope.defined.insert(variable.name);
+                Binding::Local {
+                    depth: 0,
+                    slot: scope.slots[&variable.name],
+                }
+            }
+            None => Binding::Global,
+        };
+        variable.binding.set(binding);
+    }
+
+    ///
+    ///  Declares every variable a block defines in the current scope. Nested
+    ///  blocks, functions, catch clauses and match arms get scopes of their
+    ///  own
+    ///
+    fn declare_statements(&mut self, stmts: &[Statement]) {
+        for stmt in stmts {
+            match stmt {
+                Statement::Let(pattern, ..)
+                | Statement::LetMut(pattern, ..)
+                | Statement::Const(pattern, ..) => {
+                    let Some(scope) = self.scopes.last_mut() else {
+                        continue;
+                    };
+                    for variable in pattern.bindings() {
+                        let slot = scope.declare_binding(variable.name);
+                        variable.binding.set(Binding::Local { depth: 0, slot });
+                        match stmt {
+                            Statement::Let(..) => {
+                                scope.immutables.insert(slot);
+                            }
+                            Statement::Const(..) => {
+                                scope.constants.insert(variable.name);
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+                Statement::Function(variable, _)
+                | Statement::Struct(variable, _)
+                | Statement::Enum(variable, _) => self.declare(variable),
+                Statement::Return(_) | Statement::Throw(..) | Statement::Expression(_) => {}
+            }
+        }
+    }
+
+    fn declare(&mut self, variable: &Variable) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.declare(variable.name);
+        }
+    }
+
+    fn resolve_statements(&mut self, stmts: &[Statement]) -> ResolveResult {
+        // Function, struct and enum declarations are hoisted to the start of
+        // their block
+        for stmt in stmts {
+            if let Statement::Function(variable, _)
+            | Statement::Struct(variable, _)
+            | Statement::Enum(variable, _) = stmt
+            {
+                self.define(variable);
+            }
+        }
+
+        for stmt in stmts {
+            match stmt {
+                Statement::Let(pattern, expr, position)
+                | Statement::LetMut(pattern, expr, position)
+                | Statement::Const(pattern, expr, position) => {
+                    self.resolve_expression(expr)?;
+                    self.resolve_pattern(pattern)?;
+                    let constant = matches!(stmt, Statement::Const(..));
+                    for variable in pattern.bindings() {
+                        self.define_binding(variable, constant, *position)?;
+                    }
+                }
+                Statement::Function(_, function) => self.resolve_function(function)?,
+                Statement::Struct(_, def) => {
+                    for (_, method) in &def.methods {
+                        self.resolve_function(method)?;
+                    }
+                }
+                Statement::Return(Some(expr))
+                | Statement::Throw(expr, _)
+                | Statement::Expression(expr) => self.resolve_expression(expr)?,
+                Statement::Return(None) | Statement::Enum(..) => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn resolve_function(&mut self, function: &Function) -> ResolveResult {
+        // Parameters take the first slots, followed by the variables that
+        // destructured parameters bind
+        let mut scope = Scope::new(true);
+        for param in &function.params {
+            scope.declare(param.name);
+        }
+        for pattern in function.params.iter().flat_map(|p| &p.pattern) {
+            for variable in pattern.bindings() {
+                scope.declare(variable.name);
+            }
+        }
+        self.scopes.push(scope);
+        self.declare_statements(&function.body.stmts);
+
+        let result = self.resolve_function_body(function);
+        let scope = self.scopes.pop().unwrap();
+        *function.locals.borrow_mut() = scope.names.into();
+        result
+    }
+
+    fn resolve_function_body(&mut self, function: &Function) -> ResolveResult {
+        // Defaults are evaluated in the call's scope, after the parameters
+        // before them are bound
+        for param in &function.params {
+            if let Some(default) = &param.default {
+                self.resolve_expression(default)?;
+            }
+            let scope = self.scopes.last_mut().unwrap();
+            scope.defined.insert(param.name);
+
+            if let Some(pattern) = &param.pattern {
+                self.resolve_pattern(pattern)?;
+                for variable in pattern.bindings() {
+                    self.define(variable);
+                }
+            }
+        }
+
+        self.resolve_statements(&function.body.stmts)
+    }
+
+    ///
+    ///  Resolves a block that is a scope of its own. A block declaring no
+    ///  variables shares the scope around it, so running it needs no scope
+    ///
+    fn resolve_block(&mut self, block: &BlockStatement) -> ResolveResult {
+        self.scopes.push(Scope::new(false));
+        self.declare_statements(&block.stmts);
+        if self.scopes.last().unwrap().names.is_empty() {
+            self.scopes.pop();
+            return self.resolve_statements(&block.stmts);
+        }
+
+        let result = self.resolve_statements(&block.stmts);
+        let scope = self.scopes.pop().unwrap();
+        *block.locals.borrow_mut() = scope.names.into();
+        result
+    }
+
+    fn resolve_catch(&mut self, catch: &CatchClause) -> ResolveResult {
+        let mut scope = Scope::new(false);
+        scope.declare(catch.name.name);
+        self.scopes.push(scope);
+        self.define(&catch.name);
+        self.declare_statements(&catch.body.stmts);
+
+        let result = self.resolve_statements(&catch.body.stmts);
+        let scope = self.scopes.pop().unwrap();
+        *catch.locals.borrow_mut() = scope.names.into();
+        result
+    }
+
+    fn resolve_match_arm(&mut self, arm: &MatchArm) -> ResolveResult {
+        // The struct and enum names a pattern uses are looked up outside the
+        // arm, before its bindings exist
+        self.resolve_pattern(&arm.pattern)?;
+
+        let mut scope = Scope::new(false);
+        for variable in arm.pattern.bindings() {
+            scope.declare(variable.name);
+        }
+        self.scopes.push(scope);
+        for variable in arm.pattern.bindings() {
+            self.define(variable);
+        }
+        self.declare_statements(&arm.body.stmts);
+
+        let result = match &arm.guard {
+            Some(guard) => self.resolve_expression(guard),
+            None => Ok(()),
+        }
+        .and_then(|_| self.resolve_statements(&arm.body.stmts));
+        let scope = self.scopes.pop().unwrap();
+        *arm.locals.borrow_mut() = scope.names.into();
+        result
+    }
+
+    fn resolve_pattern(&mut self, pattern: &Pattern) -> ResolveResult {
+        match pattern {
+            Pattern::Struct(name, fields, position) => {
+                self.lookup(name, *position)?;
+                for (_, pattern) in fields {
+                    self.resolve_pattern(pattern)?;
+                }
+                Ok(())
+            }
+            Pattern::Variant(name, _, items, position) => {
+                self.lookup(name, *position)?;
+                for item in items {
+                    self.resolve_pattern(item)?;
+                }
+                Ok(())
+            }
+            Pattern::Array(items, rest) => {
+                for item in items.iter().chain(rest.as_deref()) {
+                    self.resolve_pattern(item)?;
+                }
+                Ok(())
+            }
+            Pattern::Tuple(items) => {
+                for item in items {
+                    self.resolve_pattern(item)?;
+                }
+                Ok(())
+            }
+            Pattern::HashMap(entries) => {
+                for (_, pattern) in entries {
+                    self.resolve_pattern(pattern)?;
+                }
+                Ok(())
+            }
+            Pattern::Wildcard | Pattern::Binding(_) | Pattern::Literal(_) => Ok(()),
+        }
+    }
+
+    fn resolve_expression(&mut self, expr: &Expression) -> ResolveResult {
+        match expr {
+            Expression::Identifier(variable, position) => self.lookup(variable, *position),
+            Expression::Assign(variable, value, position) => {
+                self.resolve_expression(value)?;
+                self.lookup(variable, *position)?;
+
+                // Global constants and immutable variables are only known, and
+                // checked, at runtime
+                if let Binding::Local { depth, slot } = variable.binding.get() {
+                    let scope = &self.scopes[self.scopes.len() - 1 - depth];
+                    if scope.constants.contains(&variable.name) {
+                        return Err(RuntimeError::new(
+                            TYPE_ERROR,
+                            format!("cannot assign to constant `{}`", variable),
+                        )
+                        .at(*position));
+                    }
+                    if scope.immutables.contains(&slot) {
+                        return Err(immutable_assignment(variable).at(*position));
+                    }
+                }
+                Ok(())
+            }
+            Expression::IndexAssign(container, idx, value, _) => {
+                self.resolve_expression(container)?;
+                self.resolve_expression(idx)?;
+                self.resolve_expression(value)
+            }
+            Expression::Prefix(_, expr, _)
+            | Expression::Field(expr, ..)
+            | Expression::OptionalField(expr, ..) => self.resolve_expression(expr),
+            Expression::FieldAssign(object, _, value, _) => {
+                self.resolve_expression(object)?;
+                self.resolve_expression(value)
+            }
+            Expression::StructLiteral(name, fields, position) => {
+                self.lookup(name, *position)?;
+                for (_, value) in fields {
+                    self.resolve_expression(value)?;
+                }
+                Ok(())
+            }
+            Expression::Infix(_, left, right, _)
+            | Expression::Index(left, right, _)
+            | Expression::OptionalIndex(left, right, _) => {
+                self.resolve_expression(left)?;
+                self.resolve_expression(right)
+            }
+            Expression::Slice(start, end) => {
+                for bound in start.iter().chain(end) {
+                    self.resolve_expression(bound)?;
+                }
+                Ok(())
+            }
+            Expression::If(branches, false_block) => {
+                for (cond, _, block) in branches {
+                    self.resolve_expression(cond)?;
+                    self.resolve_block(block)?;
+                }
+                match false_block {
+                    Some(false_block) => self.resolve_block(false_block),
+                    None => Ok(()),
+                }
+            }
+            Expression::Ternary(cond, true_expr, false_expr, _) => {
+                self.resolve_expression(cond)?;
+                self.resolve_expression(true_expr)?;
+                self.resolve_expression(false_expr)
+            }
+            Expression::Try(body, catch, finally) => {
+                self.resolve_block(body)?;
+                if let Some(catch) = catch {
+                    self.resolve_catch(catch)?;
+                }
+                match finally {
+                    Some(finally) => self.resolve_block(finally),
+                    None => Ok(()),
+                }
+            }
+            Expression::Match(subject, arms, _) => {
+                self.resolve_expression(subject)?;
+                for arm in arms {
+                    self.resolve_match_arm(arm)?;
+                }
+                Ok(())
+            }
+            Expression::FunctionLiteral(function) => self.resolve_function(function),
+            Expression::Block(block) => self.resolve_block(block),
+            Expression::ArrayLiteral(members) | Expression::TupleLiteral(members) => {
+                for member in members {
+                    self.resolve_expression(member)?;
+                }
+                Ok(())
+            }
+            Expression::HashMapLiteral(entries) => {
+                for (key, value, _) in entries {
+                    self.resolve_expression(key)?;
+                    self.resolve_expression(value)?;
+                }
+                Ok(())
+            }
+            Expression::Call(func, args, _)
+            | Expression::OptionalCall(func, args, _)
+            | Expression::MethodCall(func, _, args, _) => {
+                self.resolve_expression(func)?;
+                for arg in args {
+                    match arg {
+                        Argument::Positional(expr)
+                        | Argument::Spread(expr)
+                        | Argument::Named(_, expr) => self.resolve_expression(expr)?,
+                    }
+                }
+                Ok(())
+            }
+            Expression::IntegerLiteral(_)
+            | Expression::String(_)
+            | Expression::Bool(_)
+            | Expression::Null => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ast::*;
+    use crate::lexer::{Lexer, Position};
+    use crate::parser::Parser;
+    use crate::resolver::resolve_program;
+    use crate::symbol::Symbol;
+
+    fn parse(input: &str) -> Program {
+        let lexer = Lexer::new(input).unwrap();
+        let mut parser = Parser::new(lexer);
+        *parser.parse_program().unwrap()
+    }
+
+    fn binding(expr: &Expression) -> Binding {
+        match expr {
+            Expression::Identifier(variable, _) => variable.binding.get(),
+            expr => panic!("Expected identifier, found {}", expr),
+        }
+    }
+
+    #[test]
+    fn test_resolve_slots() {
+        let program = parse("fn f(a, b) { let c = a + b; fn() { c + a + g } }");
+        resolve_program(&program).unwrap();
+
+        let Statement::Function(_, function) = &program.stmts[0] else {
+            panic!("Expected function declaration");
+        };
+        let locals: Vec<Symbol> = function.locals.borrow().to_vec();
+        assert_eq!(locals, ["a", "b", "c"].map(Symbol::intern));
+
+        let Statement::Let(Pattern::Binding(c), init, _) = &function.body.stmts[0] else {
+            panic!("Expected let statement");
+        };
+        assert_eq!(c.binding.get(), Binding::Local { depth: 0, slot: 2 });
+        let Expression::Infix(_, a, b, _) = &**init else {
+            panic!("Expected infix expression");
+        };
+        assert_eq!(binding(a), Binding::Local { depth: 0, slot: 0 });
+        assert_eq!(binding(b), Binding::Local { depth: 0, slot: 1 });
+
+        let Statement::Expression(closure) = &function.body.stmts[1] else {
+            panic!("Expected expression statement");
+        };
+        let Expression::FunctionLiteral(closure) = &**closure else {
+            panic!("Expected function literal");
+        };
+        let Statement::Expression(sum) = &closure.body.stmts[0] else {
+            panic!("Expected expression statement");
+        };
+        let Expression::Infix(_, c_plus_a, g, _) = &**sum else {
+            panic!("Expected infix expression");
+        };
+        let Expression::Infix(_, c, a, _) = &**c_plus_a else {
+            panic!("Expected infix expression");
+        };
+        assert_eq!(binding(c), Binding::Local { depth: 1, slot: 2 });
+        assert_eq!(binding(a), Binding::Local { depth: 1, slot: 0 });
+        assert_eq!(binding(g), Binding::Global);
+    }
+
+    #[test]
+    fn test_resolve_block_scopes() {
+        let program = parse("fn f(a) { if (a) { let b = a; b } else { a } }");
+        resolve_program(&program).unwrap();
+
+        let Statement::Function(_, function) = &program.stmts[0] else {
+            panic!("Expected function declaration");
+        };
+        let locals: Vec<Symbol> = function.locals.borrow().to_vec();
+        assert_eq!(locals, [Symbol::intern("a")]);
+
+        let Statement::Expression(expr) = &function.body.stmts[0] else {
+            panic!("Expected expression statement");
+        };
+        let Expression::If(branches, Some(false_block)) = &**expr else {
+            panic!("Expected if expression");
+        };
+        let (_, _, true_block) = &branches[0];
+        let locals: Vec<Symbol> = true_block.locals.borrow().to_vec();
+        assert_eq!(locals, [Symbol::intern("b")]);
+        assert!(false_block.locals.borrow().is_empty());
+
+        let Statement::Let(Pattern::Binding(b), init, _) = &true_block.stmts[0] else {
+            panic!("Expected let statement");
+        };
+        assert_eq!(b.binding.get(), Binding::Local { depth: 0, slot: 0 });
+        assert_eq!(binding(init), Binding::Local { depth: 1, slot: 0 });
+
+        // A block without variables of its own runs in the function's scope
+        let Statement::Expression(a) = &false_block.stmts[0] else {
+            panic!("Expected expression statement");
+        };
+        assert_eq!(binding(a), Binding::Local { depth: 0, slot: 0 });
+    }
+
+    #[test]
+    fn test_resolve_use_before_definition() {
+        let test_cases = [
+            ("fn f() { x; let x = 1; }", "x", 1, 10),
+            ("fn f(a = b, b = 1) { a }", "b", 1, 10),
+            ("let f = fn() { y = 2; let y = 1; }", "y", 1, 18),
+            (
+                "fn f() {\n  try { 1 } catch (e) { z };\n  let z = 1;\n}",
+                "z",
+                2,
+                25,
+            ),
+            ("fn f() { let w = w + 1; }", "w", 1, 18),
+            ("fn f() { let [u, v] = [v, 1]; }", "v", 1, 24),
+            ("fn f([a] = b, b = 1) { a }", "b", 1, 12),
+        ];
+
+        for (input, name, line, column) in test_cases {
+            let err = resolve_program(&parse(input)).unwrap_err();
+            assert_eq!(
+                err.message,
+                format!("variable `{}` used before its definition", name)
+            );
+            assert_eq!(err.position, Some(Position { line, column }));
+        }
+    }
+
+    #[test]
+    fn test_resolve_valid_programs() {
+        let test_cases = [
+            "x; let x = 1;",
+            "fn f() { let g = fn() { y }; let y = 1; g() }",
+            "fn f() { g(); fn g() { 1 } }",
+            "fn f(a, b = a) { let a = b; a }",
+            "fn f(c) { let mut v = 0; if (c) { let v = 1; v } else { v = 2 }; v }",
+            "let v = { let t = 1; fn g() { t } g() };",
+            "try { 1 } catch (e) { let m = e; m }",
+            "fn f([a, b], c = a + b) { let {d, e} = c; d + e }",
+            "fn f(x) { match (x) { [a, ...r] if a > 0 => { let b = a; b }, a => a } }",
+            "fn f() { const a = [1]; a[0] = 2; let g = fn() { let mut a = 1; a = 2; }; }",
+            "fn f() { let a = 1; let mut a = 2; a = 3; }",
+            "fn f() { let mut a = 1; let g = fn() { a = 2 }; let a = 3; }",
+            "fn f(a) { a = 2; let g = fn() { let a = 1; a }; }",
+            "const c = 1; fn f() { const c = 2; c }",
+        ];
+
+        for input in test_cases {
+            assert!(resolve_program(&parse(input)).is_ok(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_resolve_constants() {
+        let test_cases = [
+            (
+                "fn f() { const a = 1; a = 2; }",
+                "cannot assign to constant `a`",
+                25,
+            ),
+            (
+                "fn f() { let g = fn() { a = 2 }; const a = 1; }",
+                "cannot assign to constant `a`",
+                27,
+            ),
+            (
+                "fn f() { const a = 1; let a = 2; }",
+                "cannot redeclare constant `a`",
+                23,
+            ),
+            (
+                "fn f() { let a = 1; const a = 2; }",
+                "cannot redeclare constant `a`",
+                10,
+            ),
+            (
+                "fn f(a) { const [a] = [1]; }",
+                "cannot redeclare constant `a`",
+                11,
+            ),
+            (
+                "fn f() { let a = 1; a = 2; }",
+                "cannot assign to immutable variable `a`, declare it with `let mut`",
+                23,
+            ),
+            (
+                "fn f() { let g = fn() { a = 2 }; let a = 1; }",
+                "cannot assign to immutable variable `a`, declare it with `let mut`",
+                27,
+            ),
+            (
+                "fn f() { let [a, {b}] = [1, {}]; if (a) { b = 2 } }",
+                "cannot assign to immutable variable `b`, declare it with `let mut`",
+                45,
+            ),
+            (
+                "fn f() { let a = 1; a = 2; let mut a = 3; }",
+                "cannot assign to immutable variable `a`, declare it with `let mut`",
+                23,
+            ),
+            (
+                "fn f() { let mut a = 1; let a = 2; a = 3; }",
+                "cannot assign to immutable variable `a`, declare it with `let mut`",
+                38,
+            ),
+        ];
+
+        for (input, message, column) in test_cases {
+            let err = resolve_program(&parse(input)).unwrap_err();
+            assert_eq!(err.message, message, "{}", input);
+            assert_eq!(
+                err.position,
+                Some(Position { line: 1, column }),
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_resolve_shadowing_warnings() {
+        let warnings = resolve_program(&parse(
+            "let x = 1;
+             fn f(a) {
+                 let x = 2;
+                 let x = 3;
+                 let b = fn() { let a = 1; let c = 2; };
+             }",
+        ))
+        .unwrap();
+
+        let messages: Vec<String> = warnings.iter().map(|w| w.to_string()).collect();
+        assert_eq!(
+            messages,
+            [
+                "warning: `x` shadows a variable of an enclosing scope at line 3, column 18",
+                "warning: `a` shadows a variable of an enclosing scope at line 5, column 33",
+            ]
+        );
+    }
+}
@@ -0,0 +1,63 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+///
+///  An interned identifier. Symbols for the same name are equal, so scopes
+///  compare and hash them as integers instead of strings
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Symbol(u32);
+
+#[derive(Default)]
+struct Interner {
+    names: Vec<Rc<str>>,
+    symbols: HashMap<Rc<str>, Symbol>,
+}
+
+thread_local! {
+    static INTERNER: RefCell<Interner> = RefCell::new(Interner::default());
+}
+
+impl Symbol {
+    pub fn intern(name: &str) -> Symbol {
+        INTERNER.with(|interner| {
+            let mut interner = interner.borrow_mut();
+            if let Some(symbol) = interner.symbols.get(name) {
+                return *symbol;
+            }
+
+            let symbol = Symbol(interner.names.len() as u32);
+            let name: Rc<str> = Rc::from(name);
+            interner.names.push(name.clone());
+            interner.symbols.insert(name, symbol);
+            symbol
+        })
+    }
+
+    pub fn name(self) -> Rc<str> {
+        INTERNER.with(|interner| interner.borrow().names[self.0 as usize].clone())
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::symbol::Symbol;
+
+    #[test]
+    fn test_intern() {
+        let a = Symbol::intern("counter");
+        let b = Symbol::intern("count");
+        assert_eq!(a, Symbol::intern("counter"));
+        assert_ne!(a, b);
+        assert_eq!(&*a.name(), "counter");
+        assert_eq!(b.to_string(), "count");
+    }
+}